@@ -0,0 +1,197 @@
+use std::io::{self, Cursor, Read};
+
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::{CompressionType, DecompressError};
+
+/// The still-compressed data of one zip entry, and how to decode it.
+pub struct ZipEntry {
+    pub kind: CompressionType,
+    pub data: Vec<u8>,
+    pub crc32: u32,
+}
+
+// a zip's entries are only reliably found through the directory at its end,
+// so the whole archive is read into memory first. without a `name`, the
+// archive must hold exactly one file
+pub fn read_zip_entry<R: Read>(
+    input: &mut R,
+    name: Option<&str>,
+) -> Result<ZipEntry, DecompressError> {
+    let mut archive = Vec::new();
+    input.read_to_end(&mut archive)?;
+    let mut archive = ZipArchive::new(Cursor::new(archive)).map_err(corrupt)?;
+
+    let files = zip_files(&mut archive)?;
+    let index = match (name, files.as_slice()) {
+        (Some(name), _) => files
+            .iter()
+            .find(|(_, file)| file == name)
+            .map(|(i, _)| *i)
+            .ok_or_else(|| DecompressError::ZipEntryNotFound { name: name.into() })?,
+        (None, [(i, _)]) => *i,
+        (None, files) => {
+            let names: Vec<&str> = files.iter().map(|(_, name)| name.as_str()).collect();
+            return Err(DecompressError::ZipEntryNeeded {
+                entries: names.join(", "),
+            });
+        }
+    };
+
+    let file = archive.by_index_raw(index).map_err(corrupt)?;
+    if file.encrypted() {
+        return Err(DecompressError::UnsupportedInput(format!(
+            "zip entry `{}` is encrypted",
+            file.name()
+        )));
+    }
+    let kind = match file.compression() {
+        CompressionMethod::Stored => CompressionType::None,
+        CompressionMethod::Deflated => CompressionType::Deflate,
+        CompressionMethod::Bzip2 => CompressionType::Bzip2,
+        CompressionMethod::Zstd => CompressionType::Zstd,
+        method => {
+            return Err(DecompressError::UnsupportedInput(format!(
+                "zip entry `{}` is compressed with {method}, which isn't supported",
+                file.name()
+            )))
+        }
+    };
+    // the sizes come from the archive, so they can be anything
+    let past_the_end = || {
+        corrupt(zip::result::ZipError::InvalidArchive(
+            "entry runs past the end of the archive",
+        ))
+    };
+    let start = usize::try_from(file.data_start()).map_err(|_| past_the_end())?;
+    let end = usize::try_from(file.compressed_size())
+        .ok()
+        .and_then(|size| start.checked_add(size))
+        .ok_or_else(past_the_end)?;
+    let crc32 = file.crc32();
+    drop(file);
+
+    let mut data = archive.into_inner().into_inner();
+    if end > data.len() {
+        return Err(past_the_end());
+    }
+    data.truncate(end);
+    data.drain(..start);
+
+    Ok(ZipEntry { kind, data, crc32 })
+}
+
+/// The names of the files in a zip archive, in the order its directory lists
+/// them.
+pub fn list_zip_entries<R: Read>(input: &mut R) -> Result<Vec<String>, DecompressError> {
+    let mut archive = Vec::new();
+    input.read_to_end(&mut archive)?;
+    let mut archive = ZipArchive::new(Cursor::new(archive)).map_err(corrupt)?;
+    let files = zip_files(&mut archive)?;
+    Ok(files.into_iter().map(|(_, name)| name).collect())
+}
+
+// each file's index and name, leaving out directories
+fn zip_files(
+    archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+) -> Result<Vec<(usize, String)>, DecompressError> {
+    (0..archive.len())
+        .map(|i| {
+            let file = archive.by_index_raw(i).map_err(corrupt)?;
+            Ok((i, file.name().to_owned(), file.is_dir()))
+        })
+        .filter(|entry| !matches!(entry, Ok((_, _, true))))
+        .map(|entry| entry.map(|(i, name, _)| (i, name)))
+        .collect()
+}
+
+/// The still-compressed data of one member of an ar archive, e.g. a `.deb`'s
+/// `data.tar.xz`. Each member is compressed on its own, if at all, so its type
+/// is detected like any other input. Without a `name`, the archive must hold
+/// exactly one member.
+pub fn read_ar_member<R: Read>(input: R, name: Option<&str>) -> Result<Vec<u8>, DecompressError> {
+    let mut archive = ar::Archive::new(input);
+    let mut members = Vec::new();
+    let mut only = Vec::new();
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry.map_err(corrupt_ar)?;
+        let member = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+        match name {
+            Some(name) if name == member => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).map_err(corrupt_ar)?;
+                return Ok(data);
+            }
+            // kept in case it turns out to be the only one
+            None if members.is_empty() => {
+                entry.read_to_end(&mut only).map_err(corrupt_ar)?;
+            }
+            _ => {}
+        }
+        members.push(member);
+    }
+
+    match name {
+        Some(name) => Err(DecompressError::ArMemberNotFound { name: name.into() }),
+        None if members.len() <= 1 => Ok(only),
+        None => Err(DecompressError::ArMemberNeeded {
+            members: members.join(", "),
+        }),
+    }
+}
+
+/// The names of the members of an ar archive, in order.
+pub fn list_ar_members<R: Read>(input: R) -> Result<Vec<String>, DecompressError> {
+    let mut archive = ar::Archive::new(input);
+    let mut members = Vec::new();
+    while let Some(entry) = archive.next_entry() {
+        let entry = entry.map_err(corrupt_ar)?;
+        members.push(String::from_utf8_lossy(entry.header().identifier()).into_owned());
+    }
+    Ok(members)
+}
+
+fn corrupt_ar(error: io::Error) -> DecompressError {
+    DecompressError::CorruptArchive {
+        format: CompressionType::Ar,
+        source: error,
+    }
+}
+
+fn corrupt(error: zip::result::ZipError) -> DecompressError {
+    DecompressError::CorruptArchive {
+        format: CompressionType::Zip,
+        source: io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+}
+
+/// Checks the data read through it against a zip entry's CRC once it ends.
+pub struct Crc32Reader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    expected: u32,
+}
+
+impl<R: Read> Crc32Reader<R> {
+    pub fn new(inner: R, expected: u32) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            expected,
+        }
+    }
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() && self.hasher.clone().finalize() != self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zip entry CRC mismatch",
+            ));
+        }
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
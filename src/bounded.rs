@@ -0,0 +1,218 @@
+use std::io::{self, BufRead, Read};
+
+use brotli::enc::StandardAlloc;
+use brotli::BrotliResult;
+use liblzma::stream::{Action, Status, Stream};
+
+use crate::error::MemoryLimitExceeded;
+
+const XZ_MAGIC_START: u8 = 0xfd;
+
+type NewStream = Box<dyn FnMut() -> Result<Stream, liblzma::stream::Error>>;
+
+/// Decodes a single xz or lzma stream and stops at its end, leaving anything
+/// after it unread in `inner`.
+pub struct XzStreamReader<R: BufRead> {
+    inner: R,
+    stream: Stream,
+    done: bool,
+    next_stream: Option<NewStream>,
+    memory_limit: Option<u64>,
+}
+
+impl<R: BufRead> XzStreamReader<R> {
+    pub fn new(inner: R, stream: Stream) -> Self {
+        Self {
+            inner,
+            stream,
+            done: false,
+            next_stream: None,
+            memory_limit: None,
+        }
+    }
+
+    /// Reports liblzma stopping at its memlimit as [`MemoryLimitExceeded`],
+    /// for streams set up with `limit` as theirs.
+    pub fn with_memory_limit(self, limit: Option<u64>) -> Self {
+        Self {
+            memory_limit: limit,
+            ..self
+        }
+    }
+
+    /// Decodes xz streams one after another like `xz -d`, each with a fresh
+    /// `Stream` from `new_stream`, skipping the stream padding between them.
+    /// Stops before anything that can't be the start of another stream,
+    /// leaving it unread.
+    pub fn concatenated<F>(inner: R, mut new_stream: F) -> Result<Self, liblzma::stream::Error>
+    where
+        F: FnMut() -> Result<Stream, liblzma::stream::Error> + 'static,
+    {
+        Ok(Self {
+            inner,
+            stream: new_stream()?,
+            done: false,
+            next_stream: Some(Box::new(new_stream)),
+            memory_limit: None,
+        })
+    }
+
+    // after the end of a stream: skips the zero padding, which comes in
+    // multiples of 4 bytes, then starts on the next stream if one follows
+    fn start_next_stream(&mut self) -> io::Result<bool> {
+        let Some(new_stream) = &mut self.next_stream else {
+            return Ok(false);
+        };
+        let mut padding = 0;
+        loop {
+            let input = self.inner.fill_buf()?;
+            let len = input.len();
+            let zeros = input.iter().take_while(|&&byte| byte == 0).count();
+            self.inner.consume(zeros);
+            padding += zeros;
+            if len == 0 || zeros < len {
+                break;
+            }
+        }
+        if padding % 4 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "xz stream padding isn't a multiple of 4 bytes",
+            ));
+        }
+        // only the first byte of the magic is sure to be buffered. if the rest
+        // doesn't match, the new stream fails on it
+        if self.inner.fill_buf()?.first() != Some(&XZ_MAGIC_START) {
+            return Ok(false);
+        }
+
+        self.stream = new_stream()?;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for XzStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+            let (before_in, before_out) = (self.stream.total_in(), self.stream.total_out());
+            let action = if eof { Action::Finish } else { Action::Run };
+            let status = self.stream.process(input, buf, action);
+            let consumed = (self.stream.total_in() - before_in) as usize;
+            let read = (self.stream.total_out() - before_out) as usize;
+            self.inner.consume(consumed);
+
+            let status = status.map_err(|e| match (e, self.memory_limit) {
+                (liblzma::stream::Error::MemLimit, Some(limit)) => {
+                    io::Error::other(MemoryLimitExceeded(limit))
+                }
+                (e, _) => e.into(),
+            })?;
+            if status == Status::StreamEnd {
+                if !self.start_next_stream()? {
+                    self.done = true;
+                    return Ok(read);
+                }
+                if read > 0 {
+                    return Ok(read);
+                }
+                continue;
+            }
+            if read > 0 {
+                return Ok(read);
+            }
+            if eof {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "premature end of stream",
+                ));
+            }
+            if consumed == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "corrupt xz stream",
+                ));
+            }
+        }
+    }
+}
+
+/// Decodes a single brotli stream and stops at its end, leaving anything after
+/// it unread in `inner`. `brotli::Decompressor` reads ahead and errors on
+/// whatever follows the stream instead.
+pub struct BrotliStreamReader<R: BufRead> {
+    inner: R,
+    state: brotli::BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>,
+    total_out: usize,
+    done: bool,
+}
+
+impl<R: BufRead> BrotliStreamReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: brotli::BrotliState::new(
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+            ),
+            total_out: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for BrotliStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let input = self.inner.fill_buf()?;
+            let eof = input.is_empty();
+            let mut available_in = input.len();
+            let mut input_offset = 0;
+            let mut available_out = buf.len();
+            let mut output_offset = 0;
+            let result = brotli::BrotliDecompressStream(
+                &mut available_in,
+                &mut input_offset,
+                input,
+                &mut available_out,
+                &mut output_offset,
+                buf,
+                &mut self.total_out,
+                &mut self.state,
+            );
+            self.inner.consume(input_offset);
+
+            match result {
+                BrotliResult::ResultSuccess => {
+                    self.done = true;
+                    return Ok(output_offset);
+                }
+                BrotliResult::NeedsMoreOutput => return Ok(output_offset),
+                BrotliResult::NeedsMoreInput if output_offset > 0 => return Ok(output_offset),
+                BrotliResult::NeedsMoreInput if eof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "premature end of stream",
+                    ))
+                }
+                BrotliResult::NeedsMoreInput => continue,
+                BrotliResult::ResultFailure => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "corrupt brotli stream",
+                    ))
+                }
+            }
+        }
+    }
+}
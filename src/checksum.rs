@@ -0,0 +1,70 @@
+use std::io::{self, Read};
+
+use sha2::Digest;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ValueEnum, strum::Display)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Xxh3,
+    Sha256,
+}
+
+/// A running hash of the decompressed bytes, in whichever algorithm was asked
+/// for.
+pub enum Checksum {
+    Crc32(crc32fast::Hasher),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Sha256(sha2::Sha256),
+}
+
+impl Checksum {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Checksum::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Xxh3 => Checksum::Xxh3(Box::default()),
+            ChecksumAlgorithm::Sha256 => Checksum::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            Checksum::Crc32(hasher) => hasher.update(buf),
+            Checksum::Xxh3(hasher) => hasher.update(buf),
+            Checksum::Sha256(hasher) => hasher.update(buf),
+        }
+    }
+
+    /// The digest as lowercase hex, like `crc32`, `xxh3sum` and `sha256sum`
+    /// print it.
+    pub fn finish(self) -> String {
+        match self {
+            Checksum::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+            Checksum::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+            Checksum::Sha256(hasher) => hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        }
+    }
+}
+
+/// Feeds everything read through it to a [`Checksum`].
+pub struct HashingReader<'a, R: Read> {
+    inner: R,
+    checksum: &'a mut Checksum,
+}
+
+impl<'a, R: Read> HashingReader<'a, R> {
+    pub fn new(inner: R, checksum: &'a mut Checksum) -> Self {
+        Self { inner, checksum }
+    }
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+/// Tallies the lines and bytes written to it, like `wc -lc`, and drops the
+/// data itself. A last line without a trailing newline isn't counted, the
+/// same as `wc`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineCounter {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+impl Write for LineCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lines += buf.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_lines_and_bytes_are_counted() -> Result<()> {
+        let mut counter = LineCounter::default();
+        counter.write_all(b"this is a test\nand so")?;
+        counter.write_all(b" is this\n\nbut not this")?;
+        assert_eq!(
+            LineCounter {
+                lines: 3,
+                bytes: 43
+            },
+            counter
+        );
+
+        Ok(())
+    }
+}
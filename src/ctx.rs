@@ -1,652 +1,5828 @@
-use std::io::{self, BufRead, Read, Write};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use atty::Stream;
+use clap::ValueEnum;
 use color_eyre::eyre::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use zstd::zstd_safe::zstd_sys::ZSTD_ErrorCode;
 
+use crate::archive::{
+    list_ar_members, list_zip_entries, read_ar_member, read_zip_entry, Crc32Reader,
+};
+use crate::bounded::{BrotliStreamReader, XzStreamReader};
+use crate::checksum::{Checksum, ChecksumAlgorithm, HashingReader};
+use crate::detect::{
+    brotli_window_bits, detect_stream_characteristics, detect_with_confidence, detect_with_peek,
+    looks_like_tar, read_prefix, CompressionType, Confidence,
+};
+use crate::error::{
+    DecompressError, InputError, MemoryLimitExceeded, OutputError, OutputLimitExceeded,
+    ZstdUnsupported, ZstdWindowTooLarge,
+};
+use crate::lzw::LzwDecoder;
+use crate::peek::PeekReader;
+use crate::registry::custom_decoder;
+use crate::sevenz::{list_7z_entries, read_7z_entry};
+use crate::verify::{TeeReader, Verifier, VerifyWriter};
 use crate::Flags;
 
-const BROTLI_BUFFER_SIZE: usize = 4096;
-const BROTLI_Q: u32 = 42;
-const BROTLI_LGWIN: u32 = 69;
+pub(crate) const BROTLI_BUFFER_SIZE: usize = 64 * 1024;
+const BROTLI_Q: u32 = 9;
+const BROTLI_LGWIN: u32 = 22;
+
+const BZIP2_LEVEL: u32 = 6;
+
+// what `gzip` itself defaults to, and `Compression::default()`
+const FLATE2_LEVEL: u32 = 6;
 
 const XZ_LEVEL: u32 = 6;
+// both the stream header and footer
+const XZ_HEADER_SIZE: u64 = 12;
 
 const ZSTD_LEVEL: i32 = 6;
+// libzstd's own default, which covers `zstd --long=27`
+pub(crate) const ZSTD_WINDOW_LOG: u32 = 27;
 
-pub fn detect_stream_characteristics<R: Read>(
-    stream: &mut R,
-    flags: &Flags,
-) -> Result<(CompressionType, Vec<u8>)> {
-    let mut buffer = [0; 6];
-    let n = stream.read(&mut buffer)?;
-    let buffer = &buffer[..n];
-    let kind = detect_compression_type(buffer, flags);
+const COPY_BUFFER_SIZE: usize = 8 * 1024;
 
-    Ok((kind, Vec::from(buffer)))
-}
+// layers --recursive decompresses in all, unless --max-depth says otherwise
+const MAX_DEPTH: u32 = 8;
 
-fn detect_compression_type(buffer: &[u8], flags: &Flags) -> CompressionType {
-    if buffer.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
-        CompressionType::Zstd
-    } else if buffer.starts_with(&[0x1f, 0x8b]) {
-        CompressionType::Gzip
-    } else if buffer.starts_with(&[0x78, 0x01]) {
-        CompressionType::Deflate
-    } else if buffer.starts_with(&[0x78, 0x9c]) {
-        CompressionType::Zlib
-    } else if buffer.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
-        CompressionType::Xz
-    } else if "brotli" == flags.hint {
-        CompressionType::Brotli
-    } else {
-        CompressionType::None
+pub(crate) const TAR_BLOCK_SIZE: usize = 512;
+
+/// Detects the compression type from the first `peek_len` bytes of `reader`
+/// and returns a reader of its decompressed contents, with the peeked bytes
+/// put back in front. Like [`decompress`](crate::decompress), input that isn't
+/// recognized as compressed is an error, and an empty input reads as empty.
+/// Anything after the end of the compressed stream is not checked for.
+/// `reader` is buffered here, so it needn't be a [`BufRead`] itself.
+pub fn auto_decoder<R: Read + 'static>(
+    reader: R,
+    peek_len: usize,
+) -> Result<Box<dyn Read>, DecompressError> {
+    let flags = Flags::default();
+    let mut reader = BufReader::new(reader);
+    let (kind, magic) = detect_with_peek(&mut reader, &flags, peek_len)?;
+    if kind == CompressionType::None && !magic.is_empty() {
+        return Err(DecompressError::UnknownFormat);
     }
-}
 
-pub struct Context<'a, R: Read, W: Write> {
-    input_compression_type: CompressionType,
-    output_compression_type: CompressionType,
+    let input = PeekReader::new(magic, reader);
+    let decompressor = decompressor(kind, input, &DecoderOptions::default())?;
+    Ok(decompressor)
+}
 
-    input_stream: &'a mut R,
-    output_stream: &'a mut W,
+/// The uncompressed size recorded in the stream's header, if there is one.
+/// gzip's ISIZE and the xz index live at the end of the stream, so those are
+/// never guessed at from a prefix; see [`gzip_uncompressed_size`] and
+/// [`xz_uncompressed_size`].
+pub fn uncompressed_size_hint(kind: CompressionType, prefix: &[u8]) -> Option<u64> {
+    match kind {
+        CompressionType::Zstd => zstd::zstd_safe::get_frame_content_size(prefix)
+            .ok()
+            .flatten(),
+        _ => None,
+    }
 }
 
-impl<'a, R: Read, W: Write> Context<'a, R, W> {
-    pub fn new_from_stream(
-        input_stream: &'a mut R,
-        output_stream: &'a mut W,
-        input_compression_type: CompressionType,
-        flags: &Flags,
-    ) -> Result<Self> {
-        Ok(Self {
-            input_compression_type,
-            output_compression_type: flags.output_type.unwrap_or(CompressionType::None),
-            input_stream,
-            output_stream,
-        })
+/// Reads ISIZE from the end of a gzip file, the uncompressed size modulo
+/// 2^32, so it wraps for anything 4 GiB or bigger. Only the last member's is
+/// read. Returns `None` if it isn't gzip.
+pub fn gzip_uncompressed_size<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    // a 10 byte header, an empty deflate block and the 8 byte trailer
+    const MIN_GZIP_SIZE: u64 = 20;
+    if file.seek(SeekFrom::End(0))? < MIN_GZIP_SIZE {
+        return Ok(None);
     }
+    let mut magic = [0; 2];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut magic)?;
+    if magic != [0x1f, 0x8b] {
+        return Ok(None);
+    }
+    let mut isize = [0; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut isize)?;
+    Ok(Some(u32::from_le_bytes(isize) as u64))
+}
 
-    pub fn translate_stream(&mut self) -> Result<()> {
-        let mut decompressor: Box<dyn Decompressor> = match self.input_compression_type {
-            CompressionType::Zstd => {
-                let decoder = zstd::Decoder::new(&mut self.input_stream)?;
-                Box::new(ZstdDecompressor(decoder))
-            }
-            CompressionType::Brotli => {
-                let decoder = brotli::Decompressor::new(&mut self.input_stream, 4096);
-                Box::new(BrotliDecompressor(decoder))
-            }
-            CompressionType::Gzip => {
-                let decoder = flate2::read::GzDecoder::new(&mut self.input_stream);
-                Box::new(GzipDecompressor(decoder))
-            }
-            CompressionType::Deflate => {
-                let decoder = flate2::read::DeflateDecoder::new(&mut self.input_stream);
-                Box::new(DeflateDecompressor(decoder))
-            }
-            CompressionType::Zlib => {
-                let decoder = flate2::read::ZlibDecoder::new(&mut self.input_stream);
-                Box::new(ZlibDecompressor(decoder))
-            }
-            CompressionType::Xz => {
-                let decoder = xz2::read::XzDecoder::new(&mut self.input_stream);
-                Box::new(XzDecompressor(decoder))
-            }
-            CompressionType::None => {
-                let decoder = &mut self.input_stream;
-                Box::new(NoneDecompressor(decoder))
-            }
-        };
+/// Sums the uncompressed sizes in the index of every stream in an xz file,
+/// walking backwards from the end. Returns `None` if it isn't well-formed xz.
+pub fn xz_uncompressed_size<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    let mut position = file.seek(SeekFrom::End(0))?;
+    let mut total: u64 = 0;
+    while position > 0 {
+        // stream padding is a multiple of four null bytes
+        let mut word = [0; 4];
+        file.seek(SeekFrom::Start(position.saturating_sub(4)))?;
+        file.read_exact(&mut word)?;
+        if word == [0; 4] {
+            position -= 4;
+            continue;
+        }
 
-        let mut compressor: Box<dyn Compressor> = match self.output_compression_type {
-            CompressionType::Zstd => {
-                let encoder =
-                    zstd::Encoder::new(&mut self.output_stream, ZSTD_LEVEL)?.auto_finish();
-                Box::new(ZstdCompressor(encoder))
-            }
-            CompressionType::Brotli => {
-                let encoder = brotli::CompressorWriter::new(
-                    &mut self.output_stream,
-                    BROTLI_BUFFER_SIZE,
-                    BROTLI_Q,
-                    BROTLI_LGWIN,
-                );
-                Box::new(BrotliCompressor(encoder))
-            }
-            CompressionType::Gzip => {
-                let encoder = flate2::write::GzEncoder::new(
-                    &mut self.output_stream,
-                    flate2::Compression::default(),
-                );
-                Box::new(GzipCompressor(encoder))
-            }
-            CompressionType::Deflate => {
-                let encoder = flate2::write::DeflateEncoder::new(
-                    &mut self.output_stream,
-                    flate2::Compression::default(),
-                );
-                Box::new(DeflateCompressor(encoder))
-            }
-            CompressionType::Zlib => {
-                let encoder = flate2::write::ZlibEncoder::new(
-                    &mut self.output_stream,
-                    flate2::Compression::default(),
-                );
-                Box::new(ZlibCompressor(encoder))
-            }
-            CompressionType::Xz => {
-                let encoder = xz2::write::XzEncoder::new(&mut self.output_stream, XZ_LEVEL);
-                Box::new(XzCompressor(encoder))
-            }
-            CompressionType::None => {
-                let encoder = &mut self.output_stream;
-                Box::new(NoneCompressor(encoder))
-            }
+        if position < 2 * XZ_HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut footer = [0; XZ_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(position - XZ_HEADER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        if footer[10..] != *b"YZ" {
+            return Ok(None);
+        }
+
+        let backward_size = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+        let index_size = (backward_size as u64 + 1) * 4;
+        let Some(index_start) = (position - XZ_HEADER_SIZE).checked_sub(index_size) else {
+            return Ok(None);
+        };
+        let mut index = vec![0; index_size as usize];
+        file.seek(SeekFrom::Start(index_start))?;
+        file.read_exact(&mut index)?;
+        let Some((blocks_size, uncompressed_size)) = parse_xz_index(&index) else {
+            return Ok(None);
         };
 
-        io::copy(&mut decompressor, &mut compressor)?;
+        let Some(stream_start) = index_start
+            .checked_sub(blocks_size)
+            .and_then(|start| start.checked_sub(XZ_HEADER_SIZE))
+        else {
+            return Ok(None);
+        };
+        let mut header = [0; 6];
+        file.seek(SeekFrom::Start(stream_start))?;
+        file.read_exact(&mut header)?;
+        if header != [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] {
+            return Ok(None);
+        }
 
-        Ok(())
+        total += uncompressed_size;
+        position = stream_start;
     }
-}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ValueEnum, strum::Display)]
-pub enum CompressionType {
-    None,
-    Brotli,
-    Deflate,
-    Gzip,
-    Xz,
-    Zlib,
-    Zstd,
-    // Lzma,
+    Ok(Some(total))
 }
 
-// Compression //
+// Returns the total size of the blocks, each padded to four bytes, and the
+// total uncompressed size
+fn parse_xz_index(index: &[u8]) -> Option<(u64, u64)> {
+    let (&indicator, mut rest) = index.split_first()?;
+    if indicator != 0 {
+        return None;
+    }
 
-trait Compressor: Write {
-    fn compress(&mut self, stream: Box<dyn Read>) -> Result<()>;
-}
+    let records = read_xz_varint(&mut rest)?;
+    let mut blocks_size: u64 = 0;
+    let mut uncompressed_size: u64 = 0;
+    for _ in 0..records {
+        let unpadded_size = read_xz_varint(&mut rest)?;
+        blocks_size = blocks_size.checked_add(unpadded_size.div_ceil(4) * 4)?;
+        uncompressed_size = uncompressed_size.checked_add(read_xz_varint(&mut rest)?)?;
+    }
 
-struct ZstdCompressor<'a, T: Write>(zstd::stream::write::AutoFinishEncoder<'a, T>);
+    Some((blocks_size, uncompressed_size))
+}
 
-impl<T: Write> Write for ZstdCompressor<'_, T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+fn read_xz_varint(buffer: &mut &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let (&byte, rest) = buffer.split_first()?;
+        *buffer = rest;
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
-    }
+    None
 }
 
-impl<T: Write> Compressor for ZstdCompressor<'_, T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
-    }
+// the optional gzip header fields. without them the header stays minimal
+#[derive(Debug, Default, Clone)]
+struct GzipHeader {
+    name: Option<String>,
+    mtime: Option<u32>,
 }
 
-struct BrotliCompressor<T: Write>(brotli::CompressorWriter<T>);
+// --wrap output is this magic, the names of the original input type and the
+// payload's type, each behind a length byte, then the payload. the
+// uncompressed length isn't known until the end of a stream, so it follows
+// the payload as 8 little-endian bytes
+const WRAP_MAGIC: &[u8] = b"cwr\x01";
+const WRAP_TRAILER_SIZE: usize = 8;
 
-impl<T: Write> Write for BrotliCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+fn write_wrap_header<W: Write>(
+    output: &mut W,
+    original: CompressionType,
+    payload: CompressionType,
+) -> io::Result<()> {
+    output.write_all(WRAP_MAGIC)?;
+    for kind in [original, payload] {
+        let value = kind.to_possible_value().expect("no variant is skipped");
+        let name = value.get_name().as_bytes();
+        output.write_all(&[name.len() as u8])?;
+        output.write_all(name)?;
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
-    }
+    Ok(())
 }
 
-impl<T: Write> Compressor for BrotliCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
+// the original input type and the payload's type
+fn read_wrap_header<R: Read>(
+    input: &mut R,
+) -> Result<(CompressionType, CompressionType), DecompressError> {
+    let mut magic = [0; WRAP_MAGIC.len()];
+    if read_prefix(input, &mut magic)? < magic.len() || magic != WRAP_MAGIC {
+        return Err(DecompressError::InvalidWrapper(
+            "the input wasn't written with --wrap".into(),
+        ));
     }
-}
-
-struct GzipCompressor<T: Write>(flate2::write::GzEncoder<T>);
 
-impl<T: Write> Write for GzipCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+    let mut kinds = [CompressionType::None; 2];
+    for kind in &mut kinds {
+        let mut len = [0];
+        input.read_exact(&mut len)?;
+        let mut name = vec![0; len[0].into()];
+        input.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name);
+        *kind = CompressionType::from_str(&name, false).map_err(|_| {
+            DecompressError::InvalidWrapper(format!("unknown compression type `{name}`"))
+        })?;
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
-    }
+    Ok((kinds[0], kinds[1]))
 }
 
-impl<T: Write> Compressor for GzipCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
-    }
+// passes everything through except the last `len` bytes, which are kept back
+// for the caller once the input is done
+struct TrailerReader<R: Read> {
+    inner: R,
+    len: usize,
+    held: Vec<u8>,
+    eof: bool,
 }
 
-struct DeflateCompressor<T: Write>(flate2::write::DeflateEncoder<T>);
-
-impl<T: Write> Write for DeflateCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+impl<R: Read> TrailerReader<R> {
+    fn new(inner: R, len: usize) -> Self {
+        Self {
+            inner,
+            len,
+            held: Vec::new(),
+            eof: false,
+        }
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+    // only complete once the input has been read to the end
+    fn trailer(&self) -> &[u8] {
+        &self.held
     }
 }
 
-impl<T: Write> Compressor for DeflateCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
-    }
-}
+impl<R: Read> Read for TrailerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.len == 0 {
+            return self.inner.read(buf);
+        }
 
-struct ZlibCompressor<T: Write>(flate2::write::ZlibEncoder<T>);
+        while self.held.len() <= self.len && !self.eof {
+            let start = self.held.len();
+            self.held.resize(start + buf.len().max(self.len), 0);
+            let result = self.inner.read(&mut self.held[start..]);
+            self.held.truncate(start + *result.as_ref().unwrap_or(&0));
+            match result {
+                Ok(n) => self.eof = n == 0,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
 
-impl<T: Write> Write for ZlibCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        let n = self.held.len().saturating_sub(self.len).min(buf.len());
+        buf[..n].copy_from_slice(&self.held[..n]);
+        self.held.drain(..n);
+        Ok(n)
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
-    }
+// brotli's quality (0-11) and window size as a power of two (10-24)
+#[derive(Debug, Clone)]
+struct BrotliParams {
+    quality: u32,
+    lgwin: u32,
 }
 
-impl<T: Write> Compressor for ZlibCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
+impl Default for BrotliParams {
+    fn default() -> Self {
+        Self {
+            quality: BROTLI_Q,
+            lgwin: BROTLI_LGWIN,
+        }
     }
 }
 
-struct XzCompressor<T: Write>(xz2::write::XzEncoder<T>);
+/// The level each format is compressed at. The defaults are fixed rather
+/// than left to each library, so a transcode gives the same output whichever
+/// version of it is linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionLevels {
+    pub bzip2: u32,
+    pub deflate: u32,
+    pub gzip: u32,
+    pub lzma: u32,
+    pub xz: u32,
+    pub zlib: u32,
+    pub zstd: i32,
+}
 
-impl<T: Write> Write for XzCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+impl Default for CompressionLevels {
+    fn default() -> Self {
+        Self {
+            bzip2: BZIP2_LEVEL,
+            deflate: FLATE2_LEVEL,
+            gzip: FLATE2_LEVEL,
+            lzma: XZ_LEVEL,
+            xz: XZ_LEVEL,
+            zlib: FLATE2_LEVEL,
+            zstd: ZSTD_LEVEL,
+        }
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+impl CompressionLevels {
+    /// One `--level` for every format, clamped to the range each one takes,
+    /// so e.g. zstd's 19 is bzip2's 9.
+    pub fn with_level(level: i32) -> Self {
+        let clamp = |kind: CompressionType| {
+            let range = kind.level_range().expect("a format with levels");
+            level.clamp(*range.start(), *range.end())
+        };
+        Self {
+            bzip2: clamp(CompressionType::Bzip2) as u32,
+            deflate: clamp(CompressionType::Deflate) as u32,
+            gzip: clamp(CompressionType::Gzip) as u32,
+            lzma: clamp(CompressionType::Lzma) as u32,
+            xz: clamp(CompressionType::Xz) as u32,
+            zlib: clamp(CompressionType::Zlib) as u32,
+            zstd: clamp(CompressionType::Zstd),
+        }
     }
 }
 
-impl<T: Write> Compressor for XzCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
-    }
+/// Detects the type of `input` and transcodes it to `output_type` in memory,
+/// with the rest of the options taken from `flags`. Input that isn't
+/// recognized is compressed as is, like the CLI does.
+pub fn transcode_bytes(
+    input: &[u8],
+    output_type: CompressionType,
+    flags: &Flags,
+) -> Result<Vec<u8>, DecompressError> {
+    let flags = Flags {
+        output_type: vec![output_type],
+        ..flags.clone()
+    };
+    let (kind, mut input_stream) = detect_stream_characteristics(input, &flags)?;
+    let mut output_stream = Vec::new();
+    Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+        .translate_stream()?;
+    Ok(output_stream)
 }
 
-struct NoneCompressor<T: Write>(T);
+/// The integrity check an xz stream is written with.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, clap::ValueEnum, strum::Display)]
+pub enum XzCheck {
+    None,
+    Crc32,
+    #[default]
+    Crc64,
+    Sha256,
+}
 
-impl<T: Write> Compressor for NoneCompressor<T> {
-    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
-        io::copy(&mut stream, &mut self.0)?;
-        Ok(())
+impl From<XzCheck> for liblzma::stream::Check {
+    fn from(check: XzCheck) -> Self {
+        match check {
+            XzCheck::None => liblzma::stream::Check::None,
+            XzCheck::Crc32 => liblzma::stream::Check::Crc32,
+            XzCheck::Crc64 => liblzma::stream::Check::Crc64,
+            XzCheck::Sha256 => liblzma::stream::Check::Sha256,
+        }
     }
 }
 
-impl<T: Write> Write for NoneCompressor<T> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
-    }
+pub struct Context<'a, R: Read, W: Write> {
+    input_compression_type: CompressionType,
+    output_compression_type: CompressionType,
+    // compressed again by each in turn, after output_compression_type
+    outer_output_types: Vec<CompressionType>,
+    decoder: DecoderOptions,
+    encoder: EncoderOptions,
+    progress: bool,
+    flush_every: Option<u64>,
+    skip_bytes: u64,
+    max_bytes: Option<u64>,
+    max_output: Option<u64>,
+    // layers to decompress in all, with --recursive
+    max_depth: Option<u32>,
+    entry: Option<String>,
+    stop: Option<Arc<AtomicBool>>,
+    wrap: bool,
+    unwrap: bool,
+    unwrapped: Option<(CompressionType, CompressionType)>,
+    checksum: Option<ChecksumAlgorithm>,
+    digest: Option<String>,
+    input_bytes: u64,
+    output_bytes: u64,
+    passthrough: bool,
+    expected_size: Option<u64>,
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
-    }
+    input_stream: &'a mut R,
+    output_stream: &'a mut W,
 }
 
-// Decompression //
+impl<'a, R: Read, W: Write> Context<'a, R, W> {
+    pub fn new_from_stream(
+        input_stream: &'a mut R,
+        output_stream: &'a mut W,
+        input_compression_type: CompressionType,
+        flags: &Flags,
+    ) -> Result<Self, DecompressError> {
+        let input_compression_type = flags.forced_input_type().unwrap_or(input_compression_type);
+        // the first compresses the data, and each of the rest what came before
+        let (output_compression_type, outer_output_types) = match flags.output_type.split_first() {
+            Some((&first, rest)) => (first, rest.to_vec()),
+            None => (CompressionType::None, Vec::new()),
+        };
+        if let Some(kind) = flags.output_type.iter().find(|kind| kind.is_input_only()) {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "{kind} is only supported as an input format"
+            )));
+        }
+        if flags.output_type.len() > 1 && flags.output_type.contains(&CompressionType::None) {
+            return Err(DecompressError::UnsupportedOutput(
+                "none can't be one of several output types".into(),
+            ));
+        }
+        // the header has room for one output type
+        if flags.wrap && !outer_output_types.is_empty() {
+            return Err(DecompressError::UnsupportedOutput(
+                "--wrap takes a single output type".into(),
+            ));
+        }
+        if let Some(level) = flags.level {
+            match output_compression_type.level_range() {
+                Some(range) if range.contains(&level) => {}
+                Some(range) => {
+                    return Err(DecompressError::UnsupportedOutput(format!(
+                        "level {} is out of range for {} output (expected {}..={})",
+                        level,
+                        output_compression_type,
+                        range.start(),
+                        range.end()
+                    )))
+                }
+                None => {
+                    return Err(DecompressError::UnsupportedOutput(format!(
+                        "{} output does not take a compression level",
+                        output_compression_type
+                    )))
+                }
+            }
+        }
 
-trait Decompressor: Read {
-    fn decompress(&mut self, stream: Box<dyn Write>) -> Result<()>;
-}
+        let dictionary = match &flags.dict {
+            Some(path) => {
+                if input_compression_type != CompressionType::Zstd
+                    && output_compression_type != CompressionType::Zstd
+                {
+                    return Err(DecompressError::UnsupportedOutput(format!(
+                        "a dictionary only applies to zstd, but this is {} -> {}",
+                        input_compression_type, output_compression_type
+                    )));
+                }
 
-struct ZstdDecompressor<'a, T: BufRead>(zstd::Decoder<'a, T>);
+                let dictionary = fs::read(path).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("failed to read dictionary {}: {e}", path.display()),
+                    )
+                })?;
+                Some(dictionary)
+            }
+            None => None,
+        };
 
-impl<T: BufRead> Read for ZstdDecompressor<'_, T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
-    }
-}
+        if (flags.gzip_name.is_some() || flags.gzip_mtime.is_some())
+            && output_compression_type != CompressionType::Gzip
+        {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "a gzip file name or mtime only applies to gzip output, not {}",
+                output_compression_type
+            )));
+        }
+        if flags.decompress_only && output_compression_type != CompressionType::None {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "--decompress-only writes uncompressed output, not {}",
+                output_compression_type
+            )));
+        }
+        if flags.brotli_quality.is_some() && output_compression_type != CompressionType::Brotli {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "a brotli quality only applies to brotli output, not {}",
+                output_compression_type
+            )));
+        }
+        if flags.brotli_window.is_some()
+            && output_compression_type != CompressionType::Brotli
+            && input_compression_type != CompressionType::Brotli
+        {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "a brotli window only applies to brotli input or output, not {} to {}",
+                input_compression_type, output_compression_type
+            )));
+        }
+        if flags.xz_check.is_some() && output_compression_type != CompressionType::Xz {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "an xz check only applies to xz output, not {}",
+                output_compression_type
+            )));
+        }
+        if flags.zstd_long.is_some() && !flags.output_type.contains(&CompressionType::Zstd) {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "long-distance matching only applies to zstd output, not {}",
+                output_compression_type
+            )));
+        }
+        // the header records the input type by its name
+        if flags.wrap && input_compression_type == CompressionType::Custom {
+            return Err(DecompressError::UnsupportedInput(
+                "a custom format has no name to record when wrapping".into(),
+            ));
+        }
+        if flags
+            .gzip_name
+            .as_ref()
+            .is_some_and(|name| name.contains('\0'))
+        {
+            return Err(DecompressError::UnsupportedOutput(
+                "a gzip file name can't contain NUL bytes".into(),
+            ));
+        }
 
-impl<T: BufRead> Decompressor for ZstdDecompressor<'_, T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
-        Ok(())
-    }
-}
+        // recompressing into the same format only burns CPU and changes the
+        // bytes, unless something about the output was asked to change
+        let passthrough = input_compression_type == output_compression_type
+            && outer_output_types.is_empty()
+            && !flags.recursive
+            && !flags.recompress
+            && flags.level.is_none()
+            && flags.gzip_name.is_none()
+            && flags.gzip_mtime.is_none()
+            && flags.brotli_quality.is_none()
+            && flags.brotli_window.is_none()
+            && flags.xz_check.is_none()
+            && flags.zstd_long.is_none()
+            && !flags.wrap
+            && !flags.unwrap
+            && flags.checksum.is_none()
+            && flags.skip_bytes.is_none()
+            && flags.max_bytes.is_none()
+            // the cap is on the decompressed data, which a copy never sees
+            && flags.max_output.is_none()
+            // a copy keeps whatever follows the stream, garbage or not
+            && !flags.ignore_trailing_garbage;
 
-struct BrotliDecompressor<T: Read>(brotli::Decompressor<T>);
+        let threads = flags.threads.map(|threads| match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get() as u32),
+            n => n,
+        });
+        let buffer_size = flags.buffer_size.unwrap_or(BROTLI_BUFFER_SIZE);
 
-impl<T: Read> Read for BrotliDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        Ok(Self {
+            input_compression_type,
+            output_compression_type,
+            outer_output_types,
+            encoder: EncoderOptions {
+                levels: flags
+                    .level
+                    .map_or_else(CompressionLevels::default, CompressionLevels::with_level),
+                dictionary: dictionary.clone(),
+                threads,
+                buffer_size,
+                gzip_header: GzipHeader {
+                    name: flags.gzip_name.clone(),
+                    mtime: flags.gzip_mtime,
+                },
+                brotli_params: BrotliParams {
+                    quality: flags.brotli_quality.unwrap_or(BROTLI_Q),
+                    lgwin: flags.brotli_window.unwrap_or(BROTLI_LGWIN),
+                },
+                xz_check: flags.xz_check.unwrap_or_default(),
+                zstd_long: flags.zstd_long,
+            },
+            decoder: DecoderOptions {
+                dictionary,
+                lzma2_dict_size: flags.dict_size,
+                threads,
+                buffer_size,
+                // so what --zstd-long writes reads back, e.g. with --verify
+                zstd_window_log: flags
+                    .long
+                    .unwrap_or(ZSTD_WINDOW_LOG)
+                    .max(flags.zstd_long.unwrap_or(0)),
+                memory_limit: flags.memory_limit,
+                brotli_window: flags.brotli_input_window(),
+                first_member_only: flags.ignore_trailing_garbage,
+            },
+            progress: flags.progress && !flags.quiet && atty::is(Stream::Stderr),
+            flush_every: flags.flush_every,
+            skip_bytes: flags.skip_bytes.unwrap_or(0),
+            max_bytes: flags.max_bytes,
+            max_output: flags.max_output,
+            max_depth: flags
+                .recursive
+                .then(|| flags.max_depth.unwrap_or(MAX_DEPTH)),
+            entry: flags.entry.clone(),
+            stop: None,
+            wrap: flags.wrap,
+            unwrap: flags.unwrap,
+            unwrapped: None,
+            checksum: flags.checksum,
+            digest: None,
+            input_bytes: 0,
+            output_bytes: 0,
+            passthrough,
+            expected_size: None,
+            input_stream,
+            output_stream,
+        })
     }
-}
 
-impl<T: Read> Decompressor for BrotliDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
-        Ok(())
+    pub fn with_expected_size(mut self, expected_size: Option<u64>) -> Self {
+        self.expected_size = expected_size;
+        self
     }
-}
-
-struct GzipDecompressor<T: Read>(flate2::read::GzDecoder<T>);
 
-impl<T: Read> Read for GzipDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+    /// Compress at these levels instead of the ones from `--level`.
+    pub fn with_levels(mut self, levels: CompressionLevels) -> Self {
+        self.encoder.levels = levels;
+        self
     }
-}
 
-impl<T: Read> Decompressor for GzipDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
-        Ok(())
+    /// Once `stop` is set, e.g. from a signal handler,
+    /// [`translate_stream`](Self::translate_stream) stops reading, finishes
+    /// the output so it's still a valid stream, and returns
+    /// [`DecompressError::Interrupted`]. It's only checked between reads, so
+    /// a read that blocks holds it up.
+    pub fn with_stop_flag(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
     }
-}
-
-struct DeflateDecompressor<T: Read>(flate2::read::DeflateDecoder<T>);
 
-impl<T: Read> Read for DeflateDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+    /// The `--checksum` digest of the decompressed data, once
+    /// [`translate_stream`](Self::translate_stream) has run to the end.
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
     }
-}
 
-impl<T: Read> Decompressor for DeflateDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
-        Ok(())
+    /// How many bytes [`translate_stream`](Self::translate_stream) read from
+    /// the input, including any prefix used for detection.
+    pub fn input_bytes(&self) -> u64 {
+        self.input_bytes
     }
-}
-
-struct ZlibDecompressor<T: Read>(flate2::read::ZlibDecoder<T>);
 
-impl<T: Read> Read for ZlibDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+    /// How many bytes [`translate_stream`](Self::translate_stream) wrote to
+    /// the output.
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
     }
-}
 
-impl<T: Read> Decompressor for ZlibDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
-        Ok(())
+    /// The original input type and the payload's type, read from the header
+    /// of a `--unwrap` input by [`translate_stream`](Self::translate_stream).
+    pub fn unwrapped_types(&self) -> Option<(CompressionType, CompressionType)> {
+        self.unwrapped
     }
-}
 
-struct XzDecompressor<T: Read>(xz2::read::XzDecoder<T>);
+    pub fn translate_stream(&mut self) -> Result<(), DecompressError> {
+        if self.passthrough {
+            return self.copy_stream();
+        }
 
-impl<T: Read> Read for XzDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
-    }
-}
+        if self.unwrap {
+            let (original, payload) = read_wrap_header(&mut *self.input_stream)?;
+            self.input_compression_type = payload;
+            self.unwrapped = Some((original, payload));
+        }
+        let mut entry_crc32 = None;
+        let mut decoder_kind = self.input_compression_type;
+        let source: Box<dyn Read + '_> = if decoder_kind == CompressionType::Zip {
+            let entry = read_zip_entry(&mut *self.input_stream, self.entry.as_deref())?;
+            entry_crc32 = Some(entry.crc32);
+            decoder_kind = entry.kind;
+            Box::new(io::Cursor::new(entry.data))
+        } else if decoder_kind == CompressionType::Ar {
+            let member = read_ar_member(&mut *self.input_stream, self.entry.as_deref())?;
+            let (kind, member) =
+                detect_stream_characteristics(io::Cursor::new(member), &Flags::default())?;
+            decoder_kind = kind;
+            Box::new(member)
+        } else if decoder_kind == CompressionType::SevenZ {
+            // the data is checked against its CRC on the way out, and
+            // --max-output applies to the decoding, not just what's written
+            let entry = read_7z_entry(
+                &mut *self.input_stream,
+                self.entry.as_deref(),
+                self.max_output,
+                self.decoder.first_member_only,
+            )?;
+            decoder_kind = CompressionType::None;
+            Box::new(io::Cursor::new(entry))
+        } else {
+            Box::new(InputErrors(&mut *self.input_stream))
+        };
+        let trailer_len = if self.unwrap { WRAP_TRAILER_SIZE } else { 0 };
+        let mut input_stream =
+            BufReader::new(CountingReader::new(TrailerReader::new(source, trailer_len)));
+        let decompressor = decompressor(decoder_kind, &mut input_stream, &self.decoder)?;
+        let decompressor: Box<dyn Read> = match entry_crc32 {
+            Some(crc32) => Box::new(Crc32Reader::new(decompressor, crc32)),
+            None => Box::new(decompressor),
+        };
+        // --recursive: while what comes out starts with a magic, decode that
+        // too. the first layer counts towards the depth
+        let mut decompressor = decompressor;
+        let mut depth = 1;
+        while let Some(max_depth) = self.max_depth {
+            let (kind, inner, confidence) =
+                detect_with_confidence(decompressor, &Flags::default())?;
+            // archives are the data, not another layer
+            if confidence != Confidence::Certain
+                || matches!(
+                    kind,
+                    CompressionType::Ar | CompressionType::SevenZ | CompressionType::Zip
+                )
+            {
+                decompressor = Box::new(inner);
+                break;
+            }
+            if depth >= max_depth {
+                return Err(DecompressError::TooDeep { depth });
+            }
+            // --brotli-window is about the input itself, not what's inside it
+            let options = DecoderOptions {
+                brotli_window: None,
+                ..self.decoder.clone()
+            };
+            decompressor = self::decompressor(kind, BufReader::new(inner), &options)?;
+            depth += 1;
+        }
 
-impl<T: Read> Decompressor for XzDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
+        let mut output_stream = CountingWriter::new(&mut *self.output_stream);
+        if self.wrap {
+            write_wrap_header(
+                &mut output_stream,
+                self.input_compression_type,
+                self.output_compression_type,
+            )?;
+        }
+        // built from the one writing the output inwards
+        let mut kinds = std::iter::once(self.output_compression_type)
+            .chain(self.outer_output_types.iter().copied())
+            .rev();
+        let mut compressor = compressor(
+            kinds.next().expect("one type at least"),
+            &mut output_stream,
+            &self.encoder,
+        )?;
+        for kind in kinds {
+            let outer = Rc::new(RefCell::new(Some(compressor)));
+            let inner = self::compressor(kind, ChainLink(outer.clone()), &self.encoder)?;
+            compressor = Box::new(ChainedCompressor { inner, outer });
+        }
+
+        let decompressor: Box<dyn Read> = match self.max_output {
+            Some(limit) => Box::new(OutputLimit::new(decompressor, limit)),
+            None => Box::new(decompressor),
+        };
+
+        let progress_bar = self.progress.then(|| progress_bar(self.expected_size));
+        let decompressor: Box<dyn Read> = match &progress_bar {
+            Some(progress_bar) => Box::new(progress_bar.wrap_read(decompressor)),
+            None => Box::new(decompressor),
+        };
+
+        let mut decompressor = CountingReader::new(decompressor);
+        let mut checksum = self.checksum.map(Checksum::new);
+        // none of the decoders can seek, so skipped bytes are decoded and
+        // dropped without being buffered
+        let result = io::copy(
+            &mut (&mut decompressor).take(self.skip_bytes),
+            &mut io::sink(),
+        )
+        .and_then(|_| {
+            let limited = (&mut decompressor).take(self.max_bytes.unwrap_or(u64::MAX));
+            let limited: Box<dyn Read> = match &mut checksum {
+                Some(checksum) => Box::new(HashingReader::new(limited, checksum)),
+                None => Box::new(limited),
+            };
+            let mut limited = Stoppable::new(limited, self.stop.as_deref());
+            let mut output = OutputErrors(&mut compressor);
+            copy(&mut limited, &mut output, self.flush_every).map(|_| limited.stopped)
+        });
+        let stopped = match result {
+            // whoever reads the output has all they wanted, e.g. `c | head`
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => {
+                return Err(DecompressError::from_codec(
+                    self.input_compression_type,
+                    decompressor.count,
+                    e,
+                ))
+            }
+            Ok(stopped) => stopped,
+        };
+        // the rest of the stream was never decoded, so whatever follows it
+        // can't be told apart from it
+        let cut_short = self
+            .max_bytes
+            .is_some_and(|max| decompressor.count >= self.skip_bytes.saturating_add(max));
+        let decompressed = decompressor.count;
+        drop(decompressor);
+        match compressor.finish() {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+        if self.wrap {
+            match output_stream.write_all(&decompressed.to_le_bytes()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result?,
+            }
+        }
+        if stopped {
+            self.output_bytes = output_stream.count;
+            return Err(DecompressError::Interrupted);
+        }
+
+        if !self.decoder.first_member_only && !cut_short && !input_stream.fill_buf()?.is_empty() {
+            return Err(DecompressError::TrailingData {
+                format: self.input_compression_type,
+            });
+        }
+        if self.unwrap {
+            let trailer = input_stream.get_ref().inner.trailer();
+            let expected = u64::from_le_bytes(trailer.try_into().map_err(|_| {
+                DecompressError::InvalidWrapper("the input ends before the length".into())
+            })?);
+            if expected != decompressed {
+                return Err(DecompressError::InvalidWrapper(format!(
+                    "the payload decompressed to {decompressed} bytes, but should have been {expected}"
+                )));
+            }
+        }
+
+        self.digest = checksum.map(Checksum::finish);
+        // whatever the buffer read past the end of the stream wasn't used
+        self.input_bytes = input_stream.get_ref().count - input_stream.buffer().len() as u64;
+        self.output_bytes = output_stream.count;
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.finish();
+        }
+
+        Ok(())
+    }
+
+    // the input is already in the output format, so it's copied as is,
+    // trailing data and all
+    fn copy_stream(&mut self) -> Result<(), DecompressError> {
+        // the expected size is the decompressed one, so it's only the size of
+        // what's copied when that isn't compressed
+        let progress_bar = self.progress.then(|| {
+            progress_bar(
+                self.expected_size
+                    .filter(|_| self.input_compression_type == CompressionType::None),
+            )
+        });
+        let input_stream: Box<dyn Read> = match &progress_bar {
+            Some(progress_bar) => Box::new(progress_bar.wrap_read(&mut *self.input_stream)),
+            None => Box::new(&mut *self.input_stream),
+        };
+        let mut input_stream = Stoppable::new(input_stream, self.stop.as_deref());
+        let count = match copy(&mut input_stream, self.output_stream, self.flush_every) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        };
+        self.input_bytes = count;
+        self.output_bytes = count;
+        if input_stream.stopped {
+            return Err(DecompressError::Interrupted);
+        }
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.finish();
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses the input and compresses it again in the same format,
+    /// checking that this reproduces the input byte for byte. That only holds
+    /// if the input was written by the same encoder with the same settings.
+    pub fn verify_stream(&mut self) -> Result<(), DecompressError> {
+        let kind = self.input_compression_type;
+        let verifier = Rc::new(RefCell::new(Verifier::default()));
+        let mut input_stream = BufReader::new(TeeReader::new(
+            InputErrors(&mut *self.input_stream),
+            verifier.clone(),
+        ));
+        {
+            let mut decompressor =
+                CountingReader::new(decompressor(kind, &mut input_stream, &self.decoder)?);
+            let mut output_stream = VerifyWriter(verifier.clone());
+            let mut compressor = compressor(kind, &mut output_stream, &self.encoder)?;
+            io::copy(&mut decompressor, &mut OutputErrors(&mut compressor))
+                .map_err(|e| DecompressError::from_codec(kind, decompressor.count, e))?;
+            compressor.finish()?;
+        }
+        // trailing bytes after the compressed stream count as a difference too
+        io::copy(&mut input_stream, &mut io::sink())?;
+
+        if let Some(offset) = verifier.borrow().mismatch() {
+            return Err(DecompressError::NotReproduced {
+                format: kind,
+                offset,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses the input, then recompresses it with every format that
+    /// can be written, at its default level. Nothing is written to the output.
+    /// The `None` result is the decompressed data itself.
+    pub fn bench_stream(&mut self) -> Result<Vec<BenchResult>, DecompressError> {
+        let kind = self.input_compression_type;
+        let mut input_stream = BufReader::new(InputErrors(&mut *self.input_stream));
+        let mut decompressor = decompressor(kind, &mut input_stream, &self.decoder)?;
+        let mut data = Vec::new();
+        if let Err(e) = decompressor.read_to_end(&mut data) {
+            return Err(DecompressError::from_codec(kind, data.len() as u64, e));
+        }
+
+        // every format at its defaults, but as fast as this machine allows
+        let options = EncoderOptions {
+            threads: self.encoder.threads,
+            buffer_size: self.encoder.buffer_size,
+            ..EncoderOptions::default()
+        };
+        let mut results = Vec::new();
+        for &output_type in CompressionType::value_variants() {
+            if output_type.is_input_only() {
+                continue;
+            }
+
+            let mut output_stream = CountingWriter::new(io::sink());
+            let start = Instant::now();
+            let mut compressor = compressor(output_type, &mut output_stream, &options)?;
+            compressor
+                .write_all(&data)
+                .and_then(|()| compressor.finish())?;
+            results.push(BenchResult {
+                kind: output_type,
+                size: output_stream.count,
+                elapsed: start.elapsed(),
+            });
+        }
+
+        Ok(results)
+    }
+
+    /// Decompresses the input to nowhere, timing it, to measure decoding on
+    /// its own. Nothing is written to the output.
+    pub fn benchmark_decode(&mut self) -> Result<DecodeBenchResult, DecompressError> {
+        let kind = self.input_compression_type;
+        let mut counted = CountingReader::new(InputErrors(&mut *self.input_stream));
+        let mut output_stream = CountingWriter::new(io::sink());
+        let start = Instant::now();
+        {
+            let mut input_stream = BufReader::new(&mut counted);
+            let mut decompressor = decompressor(kind, &mut input_stream, &self.decoder)?;
+            io::copy(&mut decompressor, &mut output_stream)
+                .map_err(|e| DecompressError::from_codec(kind, output_stream.count, e))?;
+        }
+
+        Ok(DecodeBenchResult {
+            kind,
+            input_size: counted.count,
+            output_size: output_stream.count,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Writes the names of the files in a zip, 7z or ar archive to the output,
+    /// one a line, the names `--entry` picks between.
+    pub fn list_entries(&mut self) -> Result<(), DecompressError> {
+        let entries = match self.input_compression_type {
+            CompressionType::Zip => list_zip_entries(&mut *self.input_stream)?,
+            CompressionType::SevenZ => list_7z_entries(&mut *self.input_stream)?,
+            CompressionType::Ar => list_ar_members(&mut *self.input_stream)?,
+            kind => {
+                return Err(DecompressError::UnsupportedInput(format!(
+                    "--list-entries needs a zip, 7z or ar archive, not {kind}"
+                )))
+            }
+        };
+        for entry in entries {
+            writeln!(self.output_stream, "{entry}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the member names of a tar archive in the decompressed stream,
+    /// one per line, instead of the archive itself.
+    pub fn list_tar_members(&mut self) -> Result<(), DecompressError> {
+        let input_compression_type = self.input_compression_type;
+        let mut input_stream = BufReader::new(InputErrors(&mut *self.input_stream));
+        let mut decompressor = CountingReader::new(decompressor(
+            input_compression_type,
+            &mut input_stream,
+            &self.decoder,
+        )?);
+
+        let mut header = [0; TAR_BLOCK_SIZE];
+        let n = read_prefix(&mut decompressor, &mut header).map_err(|e| {
+            DecompressError::from_codec(input_compression_type, decompressor.count, e)
+        })?;
+        if !looks_like_tar(&header[..n]) {
+            return Err(DecompressError::NotTar {
+                format: input_compression_type,
+            });
+        }
+
+        let mut archive = tar::Archive::new(header[..n].chain(&mut decompressor));
+        let mut output_stream = OutputErrors(&mut *self.output_stream);
+        let result = archive.entries().and_then(|entries| {
+            for entry in entries {
+                writeln!(output_stream, "{}", entry?.path()?.display())?;
+            }
+            Ok(())
+        });
+        result.map_err(|e| {
+            DecompressError::from_codec(input_compression_type, decompressor.count, e)
+        })?;
+
+        Ok(())
+    }
+}
+
+/// How one format did in [`Context::bench_stream`].
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub kind: CompressionType,
+    pub size: u64,
+    pub elapsed: Duration,
+}
+
+/// How fast [`Context::benchmark_decode`] got through the input.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeBenchResult {
+    pub kind: CompressionType,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub elapsed: Duration,
+}
+
+impl DecodeBenchResult {
+    /// Compressed bytes read a second.
+    pub fn input_rate(&self) -> f64 {
+        self.input_size as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Decompressed bytes written a second.
+    pub fn output_rate(&self) -> f64 {
+        self.output_size as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+// how every stream is decoded, whatever its format, taken from the flags once
+#[derive(Debug, Clone)]
+struct DecoderOptions {
+    dictionary: Option<Vec<u8>>,
+    // raw LZMA2 has no header to take it from
+    lzma2_dict_size: Option<u32>,
+    threads: Option<u32>,
+    buffer_size: usize,
+    zstd_window_log: u32,
+    memory_limit: Option<u64>,
+    // the window a brotli input must declare, from --brotli-window
+    brotli_window: Option<u32>,
+    // --ignore-trailing-garbage
+    first_member_only: bool,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self {
+            dictionary: None,
+            lzma2_dict_size: None,
+            threads: None,
+            buffer_size: BROTLI_BUFFER_SIZE,
+            zstd_window_log: ZSTD_WINDOW_LOG,
+            memory_limit: None,
+            brotli_window: None,
+            first_member_only: false,
+        }
+    }
+}
+
+// Every decoder but LZW and brotli's default one reads exactly up to the end
+// of its stream, so the caller can tell if anything follows it. With
+// `first_member_only`, concatenated gzip members, bzip2 and xz streams and zstd
+// frames after the first are left unread too.
+fn decompressor<'s, R: BufRead + 's>(
+    kind: CompressionType,
+    mut input: R,
+    options: &DecoderOptions,
+) -> Result<Box<dyn Decompressor + 's>, DecompressError> {
+    let DecoderOptions {
+        ref dictionary,
+        lzma2_dict_size,
+        threads,
+        buffer_size,
+        zstd_window_log,
+        memory_limit,
+        brotli_window,
+        first_member_only,
+    } = *options;
+    let dictionary = dictionary.as_deref();
+    // neither has an end marker, so whatever follows the stream is decoded as
+    // more of it
+    if first_member_only && matches!(kind, CompressionType::LzwCompress | CompressionType::Snappy) {
+        return Err(DecompressError::UnsupportedInput(format!(
+            "{kind} streams have no end marker, so --ignore-trailing-garbage can't find where one stops"
+        )));
+    }
+    if let Some(expected) = brotli_window.filter(|_| kind == CompressionType::Brotli) {
+        check_brotli_window(&mut input, expected)?;
+    }
+    // liblzma stops at its memlimit rather than allocating past it
+    let memlimit = memory_limit.unwrap_or(u64::MAX);
+    let decompressor: Box<dyn Decompressor> = match kind {
+        CompressionType::Zstd => {
+            let decoder = ZstdDecompressor::new(
+                input,
+                dictionary,
+                zstd_window_log,
+                memory_limit,
+                first_member_only,
+            )?;
+            Box::new(decoder)
+        }
+        // `brotli::Decompressor` reads ahead and errors on anything after the
+        // stream, which is exactly right unless that's meant to be ignored
+        CompressionType::Brotli if first_member_only => {
+            Box::new(BrotliDecompressor(BrotliStreamReader::new(input)))
+        }
+        CompressionType::Brotli => {
+            let decoder = brotli::Decompressor::new(input, buffer_size);
+            Box::new(BrotliDecompressor(decoder))
+        }
+        CompressionType::Bzip2 if first_member_only => {
+            Box::new(Bzip2Decompressor(bzip2::bufread::BzDecoder::new(input)))
+        }
+        // pbzip2 and `cat a.bz2 b.bz2` both write several streams
+        CompressionType::Bzip2 => {
+            let decoder = bzip2::bufread::MultiBzDecoder::new(input);
+            Box::new(Bzip2Decompressor(decoder))
+        }
+        CompressionType::Gzip if first_member_only => {
+            Box::new(GzipDecompressor(flate2::bufread::GzDecoder::new(input)))
+        }
+        CompressionType::Gzip => {
+            let decoder = flate2::bufread::MultiGzDecoder::new(input);
+            Box::new(GzipDecompressor(decoder))
+        }
+        CompressionType::Deflate => {
+            let decoder = flate2::bufread::DeflateDecoder::new(input);
+            Box::new(DeflateDecompressor(decoder))
+        }
+        CompressionType::Zlib => {
+            let decoder = flate2::bufread::ZlibDecoder::new(input);
+            Box::new(ZlibDecompressor(decoder))
+        }
+        CompressionType::Xz => {
+            let new_stream = move || match threads {
+                // liblzma only splits the work when the stream was written in
+                // blocks, e.g. by `xz -T0`, and otherwise decodes on one thread
+                Some(threads) if threads > 1 => liblzma::stream::MtStreamBuilder::new()
+                    .threads(threads)
+                    .memlimit_threading(memlimit)
+                    .memlimit_stop(memlimit)
+                    .decoder(),
+                _ => liblzma::stream::Stream::new_stream_decoder(memlimit, 0),
+            };
+            // the threaded decoder can't be told to carry on past the first
+            // stream itself, so both start a fresh one for each
+            let decoder = if first_member_only {
+                XzStreamReader::new(input, new_stream()?)
+            } else {
+                XzStreamReader::concatenated(input, new_stream)?
+            };
+            Box::new(XzDecompressor(decoder.with_memory_limit(memory_limit)))
+        }
+        CompressionType::Lzma => {
+            let stream = liblzma::stream::Stream::new_lzma_decoder(memlimit)?;
+            let decoder = XzStreamReader::new(input, stream).with_memory_limit(memory_limit);
+            Box::new(LzmaDecompressor(decoder))
+        }
+        // liblzma checks each member's CRC32 and sizes from its trailer
+        CompressionType::Lzip => {
+            let flags = if first_member_only {
+                0
+            } else {
+                liblzma::stream::CONCATENATED
+            };
+            let stream = liblzma::stream::Stream::new_lzip_decoder(memlimit, flags)?;
+            let decoder = XzStreamReader::new(input, stream).with_memory_limit(memory_limit);
+            Box::new(LzipDecompressor(decoder))
+        }
+        // raw LZMA2 has no header to take the dictionary size from
+        CompressionType::Lzma2 => {
+            let dict_size = lzma2_dict_size.ok_or(DecompressError::MissingDictSize)?;
+            // the raw decoder takes no memlimit, but the dictionary is most of
+            // what it allocates
+            if let Some(limit) = memory_limit.filter(|&limit| u64::from(dict_size) > limit) {
+                return Err(DecompressError::MemoryLimitExceeded { limit });
+            }
+            let mut options = liblzma::stream::LzmaOptions::new();
+            options.dict_size(dict_size);
+            let mut filters = liblzma::stream::Filters::new();
+            filters.lzma2(&options);
+            let stream = liblzma::stream::Stream::new_raw_decoder(&filters)?;
+            Box::new(Lzma2Decompressor(XzStreamReader::new(input, stream)))
+        }
+        CompressionType::LzwCompress => {
+            let decoder = LzwDecoder::new(input);
+            Box::new(LzwCompressDecompressor(decoder))
+        }
+        CompressionType::Lz4 => {
+            let decoder = lz4_flex::frame::FrameDecoder::new(input);
+            Box::new(Lz4Decompressor(decoder))
+        }
+        CompressionType::Snappy => {
+            let decoder = snap::read::FrameDecoder::new(input);
+            Box::new(SnappyDecompressor(decoder))
+        }
+        CompressionType::None => Box::new(NoneDecompressor(input)),
+        CompressionType::Custom => Box::new(CustomDecompressor(custom_decoder(input)?)),
+        // an archive, not a stream. translate_stream takes the entry out
+        // first and decodes that
+        CompressionType::Ar | CompressionType::SevenZ | CompressionType::Zip => {
+            return Err(DecompressError::UnsupportedInput(format!(
+                "{kind} archives can only be extracted"
+            )))
+        }
+    };
+
+    Ok(decompressor)
+}
+
+// how every stream is encoded, whatever its format, taken from the flags once
+#[derive(Debug, Clone)]
+struct EncoderOptions {
+    levels: CompressionLevels,
+    dictionary: Option<Vec<u8>>,
+    threads: Option<u32>,
+    buffer_size: usize,
+    gzip_header: GzipHeader,
+    brotli_params: BrotliParams,
+    xz_check: XzCheck,
+    // --zstd-long's window log, when long-distance matching is on
+    zstd_long: Option<u32>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            levels: CompressionLevels::default(),
+            dictionary: None,
+            threads: None,
+            buffer_size: BROTLI_BUFFER_SIZE,
+            gzip_header: GzipHeader::default(),
+            brotli_params: BrotliParams::default(),
+            xz_check: XzCheck::default(),
+            zstd_long: None,
+        }
+    }
+}
+
+fn compressor<'s, W: Write + 's>(
+    kind: CompressionType,
+    output: W,
+    options: &EncoderOptions,
+) -> Result<Box<dyn Compressor + 's>, DecompressError> {
+    let EncoderOptions {
+        ref levels,
+        ref dictionary,
+        threads,
+        buffer_size,
+        ref gzip_header,
+        ref brotli_params,
+        xz_check,
+        zstd_long,
+    } = *options;
+    let dictionary = dictionary.as_deref();
+    let compressor: Box<dyn Compressor> = match kind {
+        CompressionType::Zstd => {
+            let encoder = ZstdCompressor::new(output, levels.zstd, dictionary, threads, zstd_long)?;
+            Box::new(encoder)
+        }
+        CompressionType::Brotli => {
+            let encoder = brotli::CompressorWriter::new(
+                LatchingWriter::new(output),
+                buffer_size,
+                brotli_params.quality,
+                brotli_params.lgwin,
+            );
+            Box::new(BrotliCompressor(encoder))
+        }
+        CompressionType::Bzip2 => {
+            let encoder =
+                bzip2::write::BzEncoder::new(output, bzip2::Compression::new(levels.bzip2));
+            Box::new(Bzip2Compressor(encoder))
+        }
+        CompressionType::Gzip => {
+            let mut builder = flate2::GzBuilder::new();
+            if let Some(name) = &gzip_header.name {
+                builder = builder.filename(name.as_str());
+            }
+            if let Some(mtime) = gzip_header.mtime {
+                builder = builder.mtime(mtime);
+            }
+            let encoder = builder.write(output, flate2::Compression::new(levels.gzip));
+            Box::new(GzipCompressor(encoder))
+        }
+        CompressionType::Deflate => {
+            let encoder = flate2::write::DeflateEncoder::new(
+                output,
+                flate2::Compression::new(levels.deflate),
+            );
+            Box::new(DeflateCompressor(encoder))
+        }
+        CompressionType::Zlib => {
+            let encoder =
+                flate2::write::ZlibEncoder::new(output, flate2::Compression::new(levels.zlib));
+            Box::new(ZlibCompressor(encoder))
+        }
+        CompressionType::Xz => {
+            let stream = liblzma::stream::Stream::new_easy_encoder(levels.xz, xz_check.into())?;
+            let encoder = liblzma::write::XzEncoder::new_stream(output, stream);
+            Box::new(XzCompressor(encoder))
+        }
+        CompressionType::Lzma => {
+            let options = liblzma::stream::LzmaOptions::new_preset(levels.lzma)?;
+            let stream = liblzma::stream::Stream::new_lzma_encoder(&options)?;
+            let encoder = liblzma::write::XzEncoder::new_stream(output, stream);
+            Box::new(LzmaCompressor(encoder))
+        }
+        CompressionType::Ar
+        | CompressionType::Custom
+        | CompressionType::Lzip
+        | CompressionType::Lzma2
+        | CompressionType::LzwCompress
+        | CompressionType::SevenZ
+        | CompressionType::Zip => {
+            return Err(DecompressError::UnsupportedOutput(format!(
+                "{kind} is only supported as an input format"
+            )))
+        }
+        CompressionType::Lz4 => {
+            let encoder = lz4_flex::frame::FrameEncoder::new(output);
+            Box::new(Lz4Compressor(encoder))
+        }
+        CompressionType::Snappy => {
+            let encoder = snap::write::FrameEncoder::new(output);
+            Box::new(SnappyCompressor(encoder))
+        }
+        CompressionType::None => Box::new(NoneCompressor(output)),
+    };
+
+    Ok(compressor)
+}
+
+fn progress_bar(expected_size: Option<u64>) -> ProgressBar {
+    match expected_size {
+        Some(size) => ProgressBar::new(size).with_style(
+            ProgressStyle::with_template(
+                "{bar:40} {percent}% {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta} left)",
+            )
+            .expect("progress template is valid"),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {bytes} ({binary_bytes_per_sec})")
+                .expect("progress template is valid"),
+        ),
+    }
+}
+
+// counts the bytes read through it, so a failure can say how far it got
+struct CountingReader<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+// marks whatever goes wrong writing, so it isn't mistaken for corrupt input
+struct OutputErrors<W: Write>(W);
+
+impl<W: Write> Write for OutputErrors<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(OutputError::wrap)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(OutputError::wrap)
+    }
+}
+
+// and whatever goes wrong reading the input itself, e.g. a dropped connection
+struct InputErrors<R: Read>(R);
+
+impl<R: Read> Read for InputErrors<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(InputError::wrap)
+    }
+}
+
+// ends the stream early once `stop` is set, so whatever reads it wraps up as
+// if the input had ended there
+struct Stoppable<'a, R: Read> {
+    inner: R,
+    stop: Option<&'a AtomicBool>,
+    stopped: bool,
+}
+
+impl<'a, R: Read> Stoppable<'a, R> {
+    fn new(inner: R, stop: Option<&'a AtomicBool>) -> Self {
+        Self {
+            inner,
+            stop,
+            stopped: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Stoppable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            self.stopped = true;
+            return Ok(0);
+        }
+        self.inner.read(buf)
+    }
+}
+
+// lets `limit` bytes through, then fails if there's any more. a decompression
+// bomb is cheap to read, so the cap is on what comes out of the decoder
+struct OutputLimit<R: Read> {
+    inner: R,
+    limit: u64,
+    remaining: u64,
+}
+
+impl<R: Read> OutputLimit<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for OutputLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(io::Error::other(OutputLimitExceeded(self.limit))),
+            };
+        }
+
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// `io::copy`, but optionally flushing along the way so a recompressor doesn't
+// sit on its output until the very end
+fn copy<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    flush_every: Option<u64>,
+) -> io::Result<u64> {
+    let Some(flush_every) = flush_every else {
+        return io::copy(reader, writer);
+    };
+
+    let mut buffer = [0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+    let mut unflushed = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+        unflushed += n as u64;
+        if unflushed >= flush_every {
+            writer.flush()?;
+            unflushed = 0;
+        }
+    }
+
+    Ok(copied)
+}
+
+// Compression //
+
+trait Compressor: Write {
+    #[allow(dead_code)]
+    fn compress(&mut self, stream: Box<dyn Read>) -> Result<()>;
+
+    /// Writes out the end of the stream. Encoders that finish on drop throw
+    /// away any error doing so, which would leave a truncated file behind.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct ZstdCompressor<'a, T: Write>(zstd::Encoder<'a, T>);
+
+impl<T: Write> ZstdCompressor<'static, T> {
+    fn new(
+        writer: T,
+        level: i32,
+        dictionary: Option<&[u8]>,
+        threads: Option<u32>,
+        long: Option<u32>,
+    ) -> io::Result<Self> {
+        let mut encoder = match dictionary {
+            Some(dictionary) => zstd::Encoder::with_dictionary(writer, level, dictionary)?,
+            None => zstd::Encoder::new(writer, level)?,
+        };
+        if let Some(window_log) = long {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window_log)?;
+        }
+        if let Some(threads) = threads {
+            // fails if libzstd was built without multithreading, in which case
+            // we just carry on single-threaded
+            let _ = encoder.multithread(threads);
+        }
+
+        Ok(Self(encoder))
+    }
+}
+
+impl<T: Write> Write for ZstdCompressor<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for ZstdCompressor<'_, T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
         Ok(())
     }
 }
 
-struct NoneDecompressor<T: Read>(T);
+struct BrotliCompressor<T: Write>(brotli::CompressorWriter<LatchingWriter<T>>);
+
+// `brotli::CompressorWriter::into_inner` ignores errors writing the end of the
+// stream, so keep hold of the first one to report it afterwards
+struct LatchingWriter<T: Write> {
+    inner: T,
+    error: Option<io::Error>,
+}
+
+impl<T: Write> LatchingWriter<T> {
+    fn new(inner: T) -> Self {
+        Self { inner, error: None }
+    }
+
+    fn latch<U>(&mut self, result: io::Result<U>) -> io::Result<U> {
+        result.map_err(|e| {
+            let latched = io::Error::new(e.kind(), e.to_string());
+            self.error.get_or_insert(e);
+            latched
+        })
+    }
+}
+
+impl<T: Write> Write for LatchingWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.latch(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.latch(result)
+    }
+}
+
+impl<T: Write> Write for BrotliCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for BrotliCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut output = self.0.into_inner();
+        match output.error.take() {
+            Some(e) => Err(e),
+            None => output.flush(),
+        }
+    }
+}
+
+struct Bzip2Compressor<T: Write>(bzip2::write::BzEncoder<T>);
+
+impl<T: Write> Write for Bzip2Compressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for Bzip2Compressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct GzipCompressor<T: Write>(flate2::write::GzEncoder<T>);
+
+impl<T: Write> Write for GzipCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for GzipCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct DeflateCompressor<T: Write>(flate2::write::DeflateEncoder<T>);
+
+impl<T: Write> Write for DeflateCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for DeflateCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct ZlibCompressor<T: Write>(flate2::write::ZlibEncoder<T>);
+
+impl<T: Write> Write for ZlibCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for ZlibCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct XzCompressor<T: Write>(liblzma::write::XzEncoder<T>);
+
+impl<T: Write> Write for XzCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for XzCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct LzmaCompressor<T: Write>(liblzma::write::XzEncoder<T>);
+
+impl<T: Write> Write for LzmaCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for LzmaCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct Lz4Compressor<T: Write>(lz4_flex::frame::FrameEncoder<T>);
+
+impl<T: Write> Write for Lz4Compressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for Lz4Compressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
+
+struct SnappyCompressor<T: Write>(snap::write::FrameEncoder<T>);
+
+impl<T: Write> Write for SnappyCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Compressor for SnappyCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    }
+}
+
+struct NoneCompressor<T: Write>(T);
+
+impl<T: Write> Compressor for NoneCompressor<T> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, &mut self.0)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Write> Write for NoneCompressor<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// feeds one compressor of a chain into the next, which stays reachable through
+// the other end so it can be finished after this one
+struct ChainLink<'s>(Rc<RefCell<Option<Box<dyn Compressor + 's>>>>);
+
+impl Write for ChainLink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.borrow_mut().as_mut() {
+            Some(outer) => outer.write(buf),
+            None => Err(io::Error::other("the next compressor is already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.borrow_mut().as_mut() {
+            Some(outer) => outer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// for --output-type a,b: `inner` writes through a ChainLink into `outer`
+struct ChainedCompressor<'s> {
+    inner: Box<dyn Compressor + 's>,
+    outer: Rc<RefCell<Option<Box<dyn Compressor + 's>>>>,
+}
+
+impl Compressor for ChainedCompressor<'_> {
+    fn compress(&mut self, mut stream: Box<dyn Read>) -> Result<()> {
+        io::copy(&mut stream, self)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let Self { inner, outer } = *self;
+        // the inner trailer has to go through before the outer one is written
+        inner.finish()?;
+        let outer = outer.borrow_mut().take();
+        outer.expect("only finished once").finish()
+    }
+}
+
+impl Write for ChainedCompressor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Decompression //
+
+trait Decompressor: Read {
+    #[allow(dead_code)]
+    fn decompress(&mut self, stream: Box<dyn Write>) -> Result<()>;
+}
+
+// brotli's header says nothing about the stream but the window it needs, and
+// the decoder takes it from there, so that's what --brotli-window is held to
+fn check_brotli_window<R: BufRead>(input: &mut R, expected: u32) -> Result<(), DecompressError> {
+    match brotli_window_bits(input.fill_buf()?) {
+        Some(found) if found != expected => {
+            Err(DecompressError::BrotliWindowMismatch { expected, found })
+        }
+        // too short, or no header at all, which the decoder reports better
+        _ => Ok(()),
+    }
+}
+
+struct ZstdDecompressor<'a, T: BufRead> {
+    decoder: zstd::Decoder<'a, T>,
+    window_log: u32,
+    // only set when it's what capped the window
+    memory_limit: Option<u64>,
+}
+
+impl<T: BufRead> ZstdDecompressor<'static, T> {
+    fn new(
+        reader: T,
+        dictionary: Option<&[u8]>,
+        window_log: u32,
+        memory_limit: Option<u64>,
+        single_frame: bool,
+    ) -> io::Result<Self> {
+        let mut decoder = match dictionary {
+            Some(dictionary) => zstd::Decoder::with_dictionary(reader, dictionary)?,
+            None => zstd::Decoder::with_buffer(reader)?,
+        };
+        // the window is what a frame's memory goes on, and it's a power of
+        // two no smaller than 1KiB
+        let memory_limit =
+            memory_limit.filter(|&limit| u64::from(window_log) > limit.max(1).ilog2().into());
+        let limit_log = memory_limit.map_or(window_log, |limit| limit.max(1).ilog2().max(10));
+        decoder.window_log_max(limit_log)?;
+
+        Ok(Self {
+            decoder: if single_frame {
+                decoder.single_frame()
+            } else {
+                decoder
+            },
+            window_log,
+            memory_limit,
+        })
+    }
+}
+
+impl<T: BufRead> Read for ZstdDecompressor<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // the zstd crate only passes on libzstd's error name, e.g. "Frame
+        // requires too much memory for decoding", so that's all there is to
+        // tell the error classes apart by
+        self.decoder.read(buf).map_err(|e| {
+            let message = e.to_string();
+            let is = |code| message == zstd_error_name(code);
+            if is(ZSTD_ErrorCode::ZSTD_error_frameParameter_windowTooLarge) {
+                match self.memory_limit {
+                    Some(limit) => io::Error::other(MemoryLimitExceeded(limit)),
+                    None => io::Error::other(ZstdWindowTooLarge(self.window_log)),
+                }
+            } else if is(ZSTD_ErrorCode::ZSTD_error_dictionary_wrong) {
+                io::Error::other(ZstdUnsupported(
+                    "a dictionary, pass the one it was written with as --dict",
+                ))
+            } else if is(ZSTD_ErrorCode::ZSTD_error_version_unsupported) {
+                io::Error::other(ZstdUnsupported(
+                    "a format version this build doesn't support",
+                ))
+            } else if is(ZSTD_ErrorCode::ZSTD_error_frameParameter_unsupported) {
+                io::Error::other(ZstdUnsupported(
+                    "a frame parameter this build doesn't support",
+                ))
+            } else {
+                e
+            }
+        })
+    }
+}
+
+// the name libzstd gives an error code, as the zstd crate reports it. asked of
+// libzstd rather than written out, so a reworded message still matches
+fn zstd_error_name(code: ZSTD_ErrorCode) -> &'static str {
+    zstd::zstd_safe::get_error_name(0usize.wrapping_sub(code as usize))
+}
+
+impl<T: BufRead> Decompressor for ZstdDecompressor<'_, T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(self, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct BrotliDecompressor<D: Read>(D);
+
+impl<D: Read> Read for BrotliDecompressor<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<D: Read> Decompressor for BrotliDecompressor<D> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct Bzip2Decompressor<D: Read>(D);
+
+impl<D: Read> Read for Bzip2Decompressor<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<D: Read> Decompressor for Bzip2Decompressor<D> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct GzipDecompressor<D: Read>(D);
+
+impl<D: Read> Read for GzipDecompressor<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<D: Read> Decompressor for GzipDecompressor<D> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct DeflateDecompressor<T: BufRead>(flate2::bufread::DeflateDecoder<T>);
+
+impl<T: BufRead> Read for DeflateDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for DeflateDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct ZlibDecompressor<T: BufRead>(flate2::bufread::ZlibDecoder<T>);
+
+impl<T: BufRead> Read for ZlibDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for ZlibDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct XzDecompressor<T: BufRead>(XzStreamReader<T>);
+
+impl<T: BufRead> Read for XzDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for XzDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct LzmaDecompressor<T: BufRead>(XzStreamReader<T>);
+
+impl<T: BufRead> Read for LzmaDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for LzmaDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct LzipDecompressor<T: BufRead>(XzStreamReader<T>);
+
+impl<T: BufRead> Read for LzipDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for LzipDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct Lzma2Decompressor<T: BufRead>(XzStreamReader<T>);
+
+impl<T: BufRead> Read for Lzma2Decompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: BufRead> Decompressor for Lzma2Decompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct LzwCompressDecompressor<T: Read>(LzwDecoder<T>);
+
+impl<T: Read> Read for LzwCompressDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> Decompressor for LzwCompressDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct Lz4Decompressor<T: Read>(lz4_flex::frame::FrameDecoder<T>);
+
+impl<T: Read> Read for Lz4Decompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> Decompressor for Lz4Decompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct SnappyDecompressor<T: Read>(snap::read::FrameDecoder<T>);
+
+impl<T: Read> Read for SnappyDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> Decompressor for SnappyDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct CustomDecompressor<'a>(Box<dyn Read + 'a>);
+
+impl Read for CustomDecompressor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Decompressor for CustomDecompressor<'_> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+struct NoneDecompressor<T: Read>(T);
+
+impl<T: Read> Read for NoneDecompressor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> Decompressor for NoneDecompressor<T> {
+    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
+        io::copy(&mut self.0, &mut stream)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use crate::detect::{
+        detect_compression_type, detect_stream_characteristics, BROTLI_TRIAL_SIZE,
+        DETECT_BUFFER_SIZE,
+    };
+    use color_eyre::eyre::eyre;
+    use color_eyre::Result;
+
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_none_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = zstd::Encoder::new(&mut compressed_stream, ZSTD_LEVEL)?.auto_finish();
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Brotli,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(
+                &mut compressed_stream,
+                BROTLI_BUFFER_SIZE,
+                BROTLI_Q,
+                BROTLI_LGWIN,
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_quality_and_window_round_trip() -> Result<()> {
+        let expected = "this is a test\n".repeat(10_000);
+        let mut sizes = Vec::new();
+        for (brotli_quality, brotli_window) in
+            [(Some(0), Some(10)), (Some(11), Some(24)), (None, None)]
+        {
+            let mut input_stream = expected.as_bytes();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![CompressionType::Brotli],
+                    brotli_quality,
+                    brotli_window,
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+            drop(ctx);
+
+            let mut decompressed = String::new();
+            brotli::Decompressor::new(output_stream.as_slice(), BROTLI_BUFFER_SIZE)
+                .read_to_string(&mut decompressed)?;
+            assert_eq!(expected, decompressed, "{brotli_quality:?}");
+            sizes.push(output_stream.len());
+        }
+        // quality 0 barely compresses compared to the others
+        assert!(sizes[0] > sizes[1] && sizes[0] > sizes[2], "{sizes:?}");
+
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                output_type: vec![CompressionType::Gzip],
+                brotli_quality: Some(5),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_input_window_is_checked() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let compress = |lgwin| -> Result<Vec<u8>> {
+            let mut compressed_stream = Vec::new();
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed_stream, 4096, 9, lgwin);
+            encoder.write_all(expected.as_bytes())?;
+            drop(encoder);
+            Ok(compressed_stream)
+        };
+        for lgwin in 10..=24 {
+            assert_eq!(
+                Some(lgwin),
+                brotli_window_bits(&compress(lgwin)?),
+                "{lgwin}"
+            );
+        }
+
+        let compressed_stream = compress(12)?;
+        for (brotli_window, detected) in [
+            (None, CompressionType::Brotli),
+            (Some(12), CompressionType::Brotli),
+            (Some(22), CompressionType::None),
+        ] {
+            let flags = crate::Flags {
+                brotli_window,
+                ..Default::default()
+            };
+            let (kind, _) = detect_stream_characteristics(compressed_stream.as_slice(), &flags)?;
+            assert_eq!(detected, kind, "{brotli_window:?}");
+        }
+
+        let decompress = |brotli_window| -> Result<Vec<u8>, DecompressError> {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Brotli,
+                &crate::Flags {
+                    quiet: true,
+                    brotli_window,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream()?;
+            Ok(output_stream)
+        };
+        assert_eq!(expected.as_bytes(), decompress(Some(12))?);
+        assert!(matches!(
+            decompress(Some(22)),
+            Err(DecompressError::BrotliWindowMismatch {
+                expected: 22,
+                found: 12
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_output_stores_name_and_mtime() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Gzip],
+                gzip_name: Some("notes.txt".into()),
+                gzip_mtime: Some(1_700_000_000),
+                ..Default::default()
+            },
+        )?;
+        ctx.translate_stream()?;
+        drop(ctx);
+
+        // FNAME is set and the name follows the fixed 10 byte header
+        assert_ne!(0, output_stream[3] & 0x08);
+        assert_eq!(1_700_000_000u32.to_le_bytes(), output_stream[4..8]);
+        assert!(output_stream[10..].starts_with(b"notes.txt\0"));
+
+        let mut decoder = flate2::read::GzDecoder::new(output_stream.as_slice());
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed)?;
+        assert_eq!(expected, decompressed);
+        assert_eq!(
+            Some(b"notes.txt".as_slice()),
+            decoder.header().and_then(|header| header.filename())
+        );
+
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Zstd],
+                gzip_name: Some("notes.txt".into()),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            let mut compressor = GzipCompressor(encoder);
+            compressor.compress(Box::new(expected.as_bytes()))?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        // and back again, detecting the gzip this time
+        let decompressed = transcode_bytes(
+            &output_stream,
+            CompressionType::None,
+            &crate::Flags::default(),
+        )?;
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deflate_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Deflate,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let encoder = flate2::write::DeflateEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            let mut compressor = DeflateCompressor(encoder);
+            compressor.compress(Box::new(expected.as_bytes()))?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Zlib,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let encoder = flate2::write::ZlibEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            let mut compressor = ZlibCompressor(encoder);
+            compressor.compress(Box::new(expected.as_bytes()))?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xz_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Xz,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut compressed_stream, XZ_LEVEL);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    // writes xz with the given check, then reads the check ID back from the
+    // stream flags in both the header and the footer
+    fn assert_xz_check(check: Option<XzCheck>, id: u8) -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Xz,
+            &crate::Flags {
+                quiet: true,
+                xz_check: check,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(id, output_stream[7] & 0x0f, "{check:?}");
+        assert_eq!(
+            id,
+            output_stream[output_stream.len() - 3] & 0x0f,
+            "{check:?}"
+        );
+        // and liblzma is happy with it
+        let mut decompressed = String::new();
+        liblzma::read::XzDecoder::new(output_stream.as_slice())
+            .read_to_string(&mut decompressed)?;
+        assert_eq!(expected, decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xz_check_none() -> Result<()> {
+        assert_xz_check(Some(XzCheck::None), 0x00)
+    }
+
+    #[test]
+    fn test_xz_check_crc32() -> Result<()> {
+        assert_xz_check(Some(XzCheck::Crc32), 0x01)
+    }
+
+    #[test]
+    fn test_xz_check_crc64() -> Result<()> {
+        assert_xz_check(Some(XzCheck::Crc64), 0x04)?;
+        // which is also the default
+        assert_xz_check(None, 0x04)
+    }
+
+    #[test]
+    fn test_xz_check_sha256() -> Result<()> {
+        assert_xz_check(Some(XzCheck::Sha256), 0x0a)
+    }
+
+    #[test]
+    fn test_xz_check_needs_xz_output() {
+        let result = transcode_bytes(
+            b"this is a test",
+            CompressionType::Gzip,
+            &crate::Flags {
+                xz_check: Some(XzCheck::Sha256),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+    }
+
+    #[test]
+    fn test_zstd_long_distance_matching() -> Result<()> {
+        // repeats further apart than level 6's window reaches
+        let mut state = 1u32;
+        let block: Vec<u8> = (0..4 * 1024 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let expected = block.repeat(2);
+
+        let short = transcode_bytes(&expected, CompressionType::Zstd, &crate::Flags::default())?;
+        let long = transcode_bytes(
+            &expected,
+            CompressionType::Zstd,
+            &crate::Flags {
+                zstd_long: Some(ZSTD_WINDOW_LOG),
+                ..Default::default()
+            },
+        )?;
+        assert!(
+            long.len() < short.len() * 3 / 4,
+            "{} {}",
+            long.len(),
+            short.len()
+        );
+        let decompressed = transcode_bytes(&long, CompressionType::None, &crate::Flags::default())?;
+        assert!(decompressed == expected);
+
+        let result = transcode_bytes(
+            b"this is a test",
+            CompressionType::Gzip,
+            &crate::Flags {
+                zstd_long: Some(ZSTD_WINDOW_LOG),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzma_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Lzma,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let options = liblzma::stream::LzmaOptions::new_preset(XZ_LEVEL)?;
+            let stream = liblzma::stream::Stream::new_lzma_encoder(&options)?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut compressed_stream, stream);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzma_decompression_works() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let options = liblzma::stream::LzmaOptions::new_preset(XZ_LEVEL)?;
+            let stream = liblzma::stream::Stream::new_lzma_encoder(&options)?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut compressed_stream, stream);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Lzma, kind);
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lz4_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Lz4,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                lz4_flex::frame::FrameEncoder::new(&mut compressed_stream).auto_finish();
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let kind = detect_compression_type(&output_stream, &flags);
+        assert_eq!(CompressionType::Lz4, kind);
+
+        let mut input_stream = output_stream.as_slice();
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut decompressed_stream, kind, &flags)?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), decompressed_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bzip2_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Bzip2,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = bzip2::write::BzEncoder::new(
+                &mut compressed_stream,
+                bzip2::Compression::new(BZIP2_LEVEL),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let kind = detect_compression_type(&output_stream, &flags);
+        assert_eq!(CompressionType::Bzip2, kind);
+
+        let mut input_stream = output_stream.as_slice();
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut decompressed_stream, kind, &flags)?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), decompressed_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_format_is_detected_by_its_magic() -> Result<()> {
+        let flags = crate::Flags::default();
+        for &kind in CompressionType::value_variants() {
+            if kind.magic_bytes().is_empty() && kind != CompressionType::Zlib {
+                continue;
+            }
+
+            let stream = if kind == CompressionType::LzwCompress {
+                include_bytes!("../fixtures/lzw.txt.Z").to_vec()
+            } else if kind == CompressionType::Lzip {
+                include_bytes!("../fixtures/lzip.txt.lz").to_vec()
+            } else if kind == CompressionType::Zip {
+                let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+                writer.start_file("test.txt", zip::write::SimpleFileOptions::default())?;
+                writer.write_all(b"this is a test")?;
+                writer.finish()?.into_inner()
+            } else if kind == CompressionType::SevenZ {
+                include_bytes!("../fixtures/lzw.txt.7z").to_vec()
+            } else if kind == CompressionType::Ar {
+                let mut builder = ar::Builder::new(Vec::new());
+                let text = b"this is a test";
+                let header = ar::Header::new(b"test.txt".to_vec(), text.len() as u64);
+                builder.append(&header, text.as_slice())?;
+                builder.into_inner()?
+            } else {
+                let mut stream = Vec::new();
+                let mut encoder = compressor(kind, &mut stream, &EncoderOptions::default())?;
+                encoder.write_all(b"this is a test")?;
+                encoder.finish()?;
+                stream
+            };
+            assert!(kind.matches_magic(&stream), "{kind}");
+            assert_eq!(kind, detect_compression_type(&stream, &flags));
+        }
+
+        assert_eq!(
+            Some(CompressionType::Gzip),
+            CompressionType::from_extension("gz")
+        );
+        assert_eq!(
+            Some(CompressionType::LzwCompress),
+            CompressionType::from_extension("Z")
+        );
+        assert_eq!(None, CompressionType::from_extension("txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detection_survives_partial_reads() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let mut input_stream = TrickleReader(&compressed_stream);
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Gzip, kind);
+        assert_eq!(DETECT_BUFFER_SIZE, input_stream.peeked().len());
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib_is_detected_at_every_level() -> Result<()> {
+        let expected = "this is a test";
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+
+        for level in 0..=9 {
+            let mut compressed_stream: Vec<u8> = Vec::new();
+            {
+                let mut encoder = flate2::write::ZlibEncoder::new(
+                    &mut compressed_stream,
+                    flate2::Compression::new(level),
+                );
+                encoder.write_all(expected.as_bytes())?;
+            }
+
+            let mut input_stream = compressed_stream.as_slice();
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::Zlib, kind, "level {level}");
+
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx =
+                Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+            ctx.translate_stream()?;
+
+            assert_eq!(expected.as_bytes(), output_stream, "level {level}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_starting_like_zlib_passes_through() -> Result<()> {
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        let inputs: [&[u8]; 2] = [
+            // a reserved block type
+            &[0x78, 0x9c, 0x06, 0x00, 0xff, 0x10, 0x20, 0x30],
+            // a stored block whose length doesn't match its complement
+            &[0x78, 0x01, 0x00, 0x12, 0x34, 0x00, 0x00, 0x42],
+        ];
+
+        for expected in inputs {
+            let mut input_stream = expected;
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::None, kind, "{expected:02x?}");
+
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx =
+                Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+            ctx.translate_stream()?;
+            assert_eq!(expected, output_stream);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_deflate_requires_hint() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::DeflateEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        assert_eq!(
+            CompressionType::None,
+            detect_compression_type(&compressed_stream, &flags)
+        );
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "deflate".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Deflate, kind);
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_is_applied() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                hint: "zstd".into(),
+                output_type: vec![CompressionType::Zstd],
+                level: Some(19),
+                ..Default::default()
+            },
+        )?;
+
+        ctx.translate_stream()?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = zstd::Encoder::new(&mut compressed_stream, 19)?.auto_finish();
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert_eq!(compressed_stream, output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_level_changes_the_output() -> Result<()> {
+        let expected: String = (0..20_000).map(|i| format!("{} ", i * 7 % 1000)).collect();
+        let gzip = |level| -> Result<Vec<u8>> {
+            let mut input_stream = expected.as_bytes();
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![CompressionType::Gzip],
+                    level,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream()?;
+            Ok(output_stream)
+        };
+
+        let fastest = gzip(Some(1))?;
+        let default = gzip(None)?;
+        let best = gzip(Some(9))?;
+        assert!(
+            fastest.len() > best.len(),
+            "{} {}",
+            fastest.len(),
+            best.len()
+        );
+        assert!(fastest.len() > default.len());
+        // no level is the same as gzip's own default of 6
+        assert_eq!(gzip(Some(6))?, default);
+
+        // the one level is clamped to what each format takes
+        let levels = CompressionLevels::with_level(19);
+        assert_eq!(19, levels.zstd);
+        assert_eq!(9, levels.gzip);
+        assert_eq!(9, levels.bzip2);
+        assert_eq!(1, CompressionLevels::with_level(0).bzip2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_of_range_level_is_rejected() {
+        for (kind, level) in [
+            (CompressionType::Zstd, 100),
+            (CompressionType::Gzip, 10),
+            (CompressionType::Xz, -1),
+            (CompressionType::Bzip2, 0),
+            (CompressionType::None, 1),
+        ] {
+            let mut input_stream = "this is a test".as_bytes();
+            let mut output_stream: Vec<u8> = Vec::new();
+
+            let ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![kind],
+                    level: Some(level),
+                    ..Default::default()
+                },
+            );
+
+            assert!(ctx.is_err(), "{kind} accepted level {level}");
+        }
+    }
+
+    #[test]
+    fn test_concatenated_gzip_members_are_all_decompressed() -> Result<()> {
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        for part in ["this is ", "a test"] {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(part.as_bytes())?;
+            encoder.finish()?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!("this is a test".as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concatenated_bzip2_streams_are_all_decompressed() -> Result<()> {
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        for part in ["this is ", "a test"] {
+            let mut encoder =
+                bzip2::write::BzEncoder::new(&mut compressed_stream, bzip2::Compression::default());
+            encoder.write_all(part.as_bytes())?;
+            encoder.finish()?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Bzip2,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!("this is a test".as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concatenated_xz_streams_are_all_decompressed() -> Result<()> {
+        // with stream padding between them and after, as `xz` allows
+        let mut compressed_stream = liblzma::encode_all(b"this is ".as_slice(), 6)?;
+        compressed_stream.extend_from_slice(&[0; 4]);
+        compressed_stream.extend_from_slice(&liblzma::encode_all(b"a test".as_slice(), 6)?);
+        compressed_stream.extend_from_slice(&[0; 8]);
+
+        for threads in [None, Some(2)] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Xz,
+                &crate::Flags {
+                    quiet: true,
+                    threads,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream()?;
+            assert_eq!("this is a test".as_bytes(), output_stream, "{threads:?}");
+        }
+
+        let gzip = transcode_bytes(&compressed_stream, CompressionType::Gzip, &Flags::default())?;
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(gzip.as_slice()).read_to_end(&mut decompressed)?;
+        assert_eq!("this is a test".as_bytes(), decompressed);
+
+        // like `xz --single-stream`
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Xz,
+            &crate::Flags {
+                quiet: true,
+                ignore_trailing_garbage: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream()?;
+        assert_eq!("this is ".as_bytes(), output_stream);
+
+        let mut bad_padding = compressed_stream.clone();
+        bad_padding.truncate(bad_padding.len() - 1);
+        let mut input_stream = bad_padding.as_slice();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut io::sink(),
+            CompressionType::Xz,
+            &Flags::default(),
+        )?
+        .translate_stream();
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_size_hint_reads_frame_header() -> Result<()> {
+        let expected = "this is a test";
+        let compressed_stream = zstd::bulk::compress(expected.as_bytes(), ZSTD_LEVEL)?;
+        let prefix = &compressed_stream[..DETECT_BUFFER_SIZE.min(compressed_stream.len())];
+        assert_eq!(
+            Some(expected.len() as u64),
+            uncompressed_size_hint(CompressionType::Zstd, prefix)
+        );
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = zstd::Encoder::new(&mut compressed_stream, ZSTD_LEVEL)?.auto_finish();
+            encoder.write_all(expected.as_bytes())?;
+        }
+        let prefix = &compressed_stream[..DETECT_BUFFER_SIZE.min(compressed_stream.len())];
+        assert_eq!(None, uncompressed_size_hint(CompressionType::Zstd, prefix));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompression_errors_name_the_format() -> Result<()> {
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut compressed_stream, XZ_LEVEL);
+            encoder.write_all("this is a test".as_bytes())?;
+        }
+        compressed_stream.truncate(compressed_stream.len() / 2);
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Xz,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let err = ctx.translate_stream().unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DecompressError::Corrupt {
+                    format: CompressionType::Xz,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(err.to_string().starts_with("corrupt Xz stream"), "{err}");
+
+        Ok(())
+    }
+
+    // some decoders report bad data as the catch-all `Other` kind
+    #[test]
+    fn test_damage_is_corrupt_in_every_format() -> Result<()> {
+        let expected: Vec<u8> = (0..100_000u32).flat_map(|n| n.to_le_bytes()).collect();
+        for &kind in CompressionType::value_variants() {
+            if kind.is_input_only() || kind == CompressionType::None {
+                continue;
+            }
+
+            let mut compressed_stream = Vec::new();
+            let mut encoder = compressor(kind, &mut compressed_stream, &EncoderOptions::default())?;
+            encoder.write_all(&expected)?;
+            encoder.finish()?;
+            for byte in &mut compressed_stream[6..22] {
+                *byte ^= 0x55;
+            }
+
+            let result = Context::new_from_stream(
+                &mut compressed_stream.as_slice(),
+                &mut io::sink(),
+                kind,
+                &crate::Flags {
+                    quiet: true,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream();
+            assert!(
+                matches!(result, Err(DecompressError::Corrupt { format, .. }) if format == kind),
+                "{kind}: {result:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    // e.g. a URL whose connection drops, whatever kind of error it gives
+    #[test]
+    fn test_input_errors_arent_corrupt_input() -> Result<()> {
+        struct FailingReader(io::ErrorKind);
+
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(self.0, "connection reset"))
+            }
+        }
+
+        for kind in [CompressionType::Gzip, CompressionType::Zstd] {
+            for error_kind in [io::ErrorKind::Other, io::ErrorKind::InvalidData] {
+                let result = Context::new_from_stream(
+                    &mut FailingReader(error_kind),
+                    &mut io::sink(),
+                    kind,
+                    &crate::Flags {
+                        quiet: true,
+                        ..Default::default()
+                    },
+                )?
+                .translate_stream();
+                assert!(
+                    matches!(&result, Err(DecompressError::Io(e)) if e.kind() == error_kind),
+                    "{kind}: {result:?}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multithreaded_zstd_round_trips() -> Result<()> {
+        let expected = "this is a test".repeat(4096);
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Zstd],
+                threads: Some(0),
+                ..Default::default()
+            },
+        )?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!(
+            expected.as_bytes(),
+            zstd::decode_all(output_stream.as_slice())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_dictionary_round_trips() -> Result<()> {
+        let expected = "this is a test";
+        let dictionary_path =
+            std::env::temp_dir().join(format!("c-test-dictionary-{}", std::process::id()));
+        fs::write(
+            &dictionary_path,
+            "this is a dictionary for a test".repeat(8),
+        )?;
+
+        let mut input_stream = expected.as_bytes();
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut compressed_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Zstd],
+                dict: Some(dictionary_path.clone()),
+                ..Default::default()
+            },
+        )?;
+        ctx.translate_stream()?;
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                dict: Some(dictionary_path.clone()),
+                ..Default::default()
+            },
+        )?;
+        ctx.translate_stream()?;
+
+        fs::remove_file(&dictionary_path)?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_frame_needing_a_dictionary_says_so() -> Result<()> {
+        // a trained dictionary, unlike raw content, has an ID the frame
+        // records
+        let samples: Vec<String> = (0..1000)
+            .map(|i| {
+                format!(
+                    "{{\"id\": {i}, \"name\": \"user{i}\", \"active\": {}}}",
+                    i % 3 == 0
+                )
+            })
+            .collect();
+        let dictionary = zstd::dict::from_samples(&samples, 4096)?;
+        let expected = samples.concat();
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                zstd::Encoder::with_dictionary(&mut compressed_stream, ZSTD_LEVEL, &dictionary)?;
+            encoder.write_all(expected.as_bytes())?;
+            encoder.finish()?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+
+        match result {
+            Err(e @ DecompressError::ZstdUnsupported { .. }) => {
+                assert!(e.to_string().contains("--dict"), "{e}")
+            }
+            result => panic!("expected a missing dictionary, got {result:?}"),
+        }
+
+        Ok(())
+    }
+
+    // v0.1's magic, which libzstd knows but this build can't decode
+    #[test]
+    fn test_unsupported_zstd_version_says_so() -> Result<()> {
+        let mut input_stream: &[u8] = &[0xfd, 0x2f, 0xb5, 0x1e, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+
+        match result {
+            Err(e @ DecompressError::ZstdUnsupported { .. }) => {
+                assert!(e.to_string().contains("version"), "{e}")
+            }
+            result => panic!("expected an unsupported version, got {result:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dictionary_is_rejected_for_other_formats() {
+        let mut input_stream = "this is a test".as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Xz],
+                dict: Some("dictionary".into()),
+                ..Default::default()
+            },
+        );
+
+        assert!(ctx.is_err());
+    }
+
+    #[test]
+    fn test_snappy_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let output_stream = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Snappy,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = snap::write::FrameEncoder::new(&mut compressed_stream);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            output_type: Vec::new(),
+            ..Default::default()
+        };
+        let kind = detect_compression_type(&output_stream, &flags);
+        assert_eq!(CompressionType::Snappy, kind);
+
+        let mut input_stream = output_stream.as_slice();
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut decompressed_stream, kind, &flags)?;
+
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), decompressed_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_lzma2_needs_dict_size() -> Result<()> {
+        let expected = b"this is a test";
+        let mut options = liblzma::stream::LzmaOptions::new_preset(6)?;
+        options.dict_size(1 << 20);
+        let mut filters = liblzma::stream::Filters::new();
+        filters.lzma2(&options);
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let stream = liblzma::stream::Stream::new_raw_encoder(&filters)?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut compressed_stream, stream);
+            encoder.write_all(expected)?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            CompressionType::None,
+            detect_compression_type(&compressed_stream, &flags)
+        );
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                input_type: Some(CompressionType::Lzma2),
+                dict_size: Some(1 << 20),
+                ..Default::default()
+            },
+        )?;
+        ctx.translate_stream()?;
+        drop(ctx);
+        assert_eq!(expected.as_slice(), output_stream);
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Lzma2,
+            &flags,
+        )?;
+        assert!(matches!(
+            ctx.translate_stream(),
+            Err(DecompressError::MissingDictSize)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xz_is_detected_from_a_short_first_read() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut compressed_stream, XZ_LEVEL);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            CompressionType::Xz,
+            detect_compression_type(&compressed_stream[..5], &flags)
+        );
+
+        // the first `read` only yields the first five bytes
+        let mut input_stream = compressed_stream[..5].chain(&compressed_stream[5..]);
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Xz, kind);
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzw_compress_decompression_works() -> Result<()> {
+        // written with a 12-bit table, so it crosses every code width change
+        // and several table clears
+        let expected = include_bytes!("../fixtures/lzw.txt");
+        let compressed_stream = include_bytes!("../fixtures/lzw.txt.Z");
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            ..Default::default()
+        };
+        let kind = detect_compression_type(compressed_stream, &flags);
+        assert_eq!(CompressionType::LzwCompress, kind);
+        assert_eq!(
+            CompressionType::Gzip,
+            detect_compression_type(&[0x1f, 0x8b, 0x08], &flags)
+        );
+
+        let mut input_stream = TrickleReader(compressed_stream);
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+
+        assert_eq!(expected.as_slice(), output_stream);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzip_decompression_works() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let compressed_stream = include_bytes!("../fixtures/lzip.txt.lz");
+
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        let kind = detect_compression_type(compressed_stream, &flags);
+        assert_eq!(CompressionType::Lzip, kind);
+
+        // a second member carries on where the first left off, like `cat`
+        let mut input_stream = TrickleReader(&compressed_stream.repeat(2));
+        let mut output_stream: Vec<u8> = Vec::new();
+        Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+            .translate_stream()?;
+        assert_eq!(expected.repeat(2).as_bytes(), output_stream);
+
+        // the trailer's CRC32 of the data, flipped
+        let mut corrupt = compressed_stream.to_vec();
+        let crc = corrupt.len() - 20;
+        corrupt[crc] ^= 0xff;
+        let mut input_stream = corrupt.as_slice();
+        let result = Context::new_from_stream(&mut input_stream, &mut io::sink(), kind, &flags)?
+            .translate_stream();
+        assert!(result.is_err());
+
+        let mut input_stream: &[u8] = b"this is a test";
+        let mut output_stream = io::sink();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                output_type: vec![CompressionType::Lzip],
+                ..flags
+            },
+        );
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzw_compress_output_is_rejected() {
+        let mut input_stream: &[u8] = b"this is a test";
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::LzwCompress],
+                ..Default::default()
+            },
+        );
+
+        assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+    }
+
+    #[test]
+    fn test_corrupt_gzip_trailer_is_an_error() -> Result<()> {
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::fast());
+            encoder.write_all(b"this is a test")?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        // the trailer is the CRC32 followed by ISIZE, the length mod 2^32
+        for offset in [8, 4] {
+            let mut corrupted = compressed_stream.clone();
+            let len = corrupted.len();
+            corrupted[len - offset] ^= 0x01;
+
+            let mut input_stream = corrupted.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &flags,
+            )?;
+
+            // the whole payload came out before the trailer was checked
+            let err = ctx.translate_stream().unwrap_err();
+            assert!(
+                matches!(err, DecompressError::Corrupt { offset: 14, .. }),
+                "{err:?}"
+            );
+            let err = color_eyre::Report::from(err);
+            assert!(format!("{err:#}").contains("checksum"), "{err:#}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_round_trips_with_any_buffer_size() -> Result<()> {
+        let expected = "this is a test".repeat(1024);
+        for buffer_size in [1, 4096, 1024 * 1024] {
+            let flags = crate::Flags {
+                quiet: true,
+                buffer_size: Some(buffer_size),
+                ..Default::default()
+            };
+
+            let mut input_stream = expected.as_bytes();
+            let mut compressed_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut compressed_stream,
+                CompressionType::None,
+                &crate::Flags {
+                    output_type: vec![CompressionType::Brotli],
+                    ..flags.clone()
+                },
+            )?
+            .translate_stream()?;
+
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Brotli,
+                &flags,
+            )?
+            .translate_stream()?;
+            assert_eq!(
+                expected.as_bytes(),
+                output_stream,
+                "buffer size {buffer_size}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_is_detected_by_trial_decode() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                brotli::CompressorWriter::new(&mut compressed_stream, BROTLI_BUFFER_SIZE, 9, 22);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            ..Default::default()
+        };
+        let mut input_stream = TrickleReader(&compressed_stream);
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Brotli, kind);
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        // plain text doesn't survive the trial, whether it fits in the trial
+        // buffer or not
+        let large = expected.repeat(BROTLI_TRIAL_SIZE / expected.len() + 1);
+        for plain in [expected, large.as_str()] {
+            let mut input_stream = plain.as_bytes();
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::None, kind);
+
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx =
+                Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+            ctx.translate_stream()?;
+            assert_eq!(plain.as_bytes(), output_stream);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leading_bom_is_skipped() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream = b"\xef\xbb\xbf".to_vec();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+
+        let (kind, _) = detect_stream_characteristics(TrickleReader(&compressed_stream), &flags)?;
+        assert_eq!(CompressionType::Gzip, kind);
+        let output_stream = transcode_bytes(&compressed_stream, CompressionType::None, &flags)?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        // text that starts with a BOM keeps it
+        let text = "\u{feff}this is a test";
+        let output_stream = transcode_bytes(text.as_bytes(), CompressionType::None, &flags)?;
+        assert_eq!(text.as_bytes(), output_stream);
+
+        // anything else has to be asked for, and is gone from the output too
+        let mut junk = b"junk\n".to_vec();
+        junk.extend_from_slice(&compressed_stream[3..]);
+        let output_stream = transcode_bytes(
+            &junk,
+            CompressionType::None,
+            &crate::Flags {
+                strip_leading: Some(5),
+                ..flags.clone()
+            },
+        )?;
+        assert_eq!(expected.as_bytes(), output_stream);
+        let output_stream = transcode_bytes(
+            text.as_bytes(),
+            CompressionType::None,
+            &crate::Flags {
+                strip_leading: Some(3),
+                ..flags
+            },
+        )?;
+        assert_eq!(b"this is a test", output_stream.as_slice());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_deflate_skips_detection() -> Result<()> {
+        let expected = "this is a test\n".repeat(100);
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::DeflateEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+        // a 513 byte stored block, whose header and length happen to read as
+        // a zlib header and a fixed huffman block
+        let mut zlib_lookalike = vec![0x78, 0x01, 0x02, 0xfe, 0xfd];
+        zlib_lookalike.extend([b'a'; 513]);
+        zlib_lookalike.extend([0x03, 0x00]);
+        assert_eq!(
+            CompressionType::Zlib,
+            detect_compression_type(&zlib_lookalike, &crate::Flags::default())
+        );
+
+        let flags = crate::Flags {
+            quiet: true,
+            raw_deflate: true,
+            ..Default::default()
+        };
+        for (input, expected) in [
+            (compressed_stream.as_slice(), expected.as_bytes()),
+            (zlib_lookalike.as_slice(), [b'a'; 513].as_slice()),
+        ] {
+            let mut input_stream = TrickleReader(input);
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::Deflate, kind);
+
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx =
+                Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+            ctx.translate_stream()?;
+            assert_eq!(expected, output_stream);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_input_type_skips_detection() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::DeflateEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let flags = crate::Flags {
+            quiet: true,
+            hint: "unknown".into(),
+            input_type: Some(CompressionType::Deflate),
+            ..Default::default()
+        };
+        let mut input_stream = TrickleReader(&compressed_stream);
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Deflate, kind);
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        // looks like zlib, but is passed through untouched when forced to none
+        let zlib_lookalike = [0x78, 0x9c, 0x01, 0x02, 0x03];
+        let flags = crate::Flags {
+            quiet: true,
+            input_type: Some(CompressionType::None),
+            ..Default::default()
+        };
+        let mut input_stream = zlib_lookalike.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Zlib,
+            &flags,
+        )?;
+        ctx.translate_stream()?;
+        assert_eq!(zlib_lookalike.as_slice(), output_stream);
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct FlushRecorder {
+        data: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed_at.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn test_closed_output_pipe_is_not_an_error() -> Result<()> {
+        let mut input_stream: &[u8] = b"this is a test";
+        let mut output_stream = ClosedPipe;
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+        ctx.translate_stream()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_benchmark_decode_measures_throughput() -> Result<()> {
+        let compressed_stream = include_bytes!("../fixtures/lzw.txt.Z");
+        let expected = include_bytes!("../fixtures/lzw.txt");
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::LzwCompress,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+        let result = ctx.benchmark_decode()?;
+        drop(ctx);
+
+        assert_eq!(CompressionType::LzwCompress, result.kind);
+        assert_eq!(compressed_stream.len() as u64, result.input_size);
+        assert_eq!(expected.len() as u64, result.output_size);
+        assert!(result.input_rate() > 0.0);
+        assert!(result.output_rate() > result.input_rate());
+        assert!(output_stream.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bench_recompresses_with_every_output_format() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?;
+        let results = ctx.bench_stream()?;
+        drop(ctx);
+
+        let kinds: Vec<_> = results.iter().map(|result| result.kind).collect();
+        assert!(!kinds.contains(&CompressionType::LzwCompress));
+        assert!(!kinds.contains(&CompressionType::Lzma2));
+        assert!(!kinds.contains(&CompressionType::Zip));
+        assert!(!kinds.contains(&CompressionType::Ar));
+        assert!(!kinds.contains(&CompressionType::Lzip));
+        assert!(!kinds.contains(&CompressionType::SevenZ));
+        assert_eq!(CompressionType::value_variants().len() - 6, kinds.len());
+        for result in results {
+            match result.kind {
+                CompressionType::None => assert_eq!(expected.len() as u64, result.size),
+                kind => assert!(result.size < expected.len() as u64, "{kind}"),
+            }
+        }
+        assert!(output_stream.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_is_the_same_when_transcoding() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut compressed_stream, 6);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        for (algorithm, digest) in [
+            (ChecksumAlgorithm::Crc32, "0d1ee7ea"),
+            (
+                ChecksumAlgorithm::Sha256,
+                "2e99758548972a8e8822ad47fa1017ff72f06f3ff6a016851f45c398732bc50c",
+            ),
+        ] {
+            for output_type in [CompressionType::None, CompressionType::Zstd] {
+                let mut input_stream = compressed_stream.as_slice();
+                let mut output_stream: Vec<u8> = Vec::new();
+                let mut ctx = Context::new_from_stream(
+                    &mut input_stream,
+                    &mut output_stream,
+                    CompressionType::Xz,
+                    &crate::Flags {
+                        quiet: true,
+                        output_type: vec![output_type],
+                        checksum: Some(algorithm),
+                        ..Default::default()
+                    },
+                )?;
+                assert_eq!(None, ctx.digest());
+                ctx.translate_stream()?;
+                assert_eq!(Some(digest), ctx.digest(), "{algorithm} to {output_type}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_endings_are_preserved() -> Result<()> {
+        // CRLF, a lone CR and LF, and DOS's end-of-file marker
+        let expected: &[u8] = b"one\r\ntwo\rthree\n\r\n\x1a\r\n\x00\r";
+        for kind in [CompressionType::None, CompressionType::Gzip] {
+            let mut compressed_stream: Vec<u8> = Vec::new();
+            let mut encoder = compressor(kind, &mut compressed_stream, &EncoderOptions::default())?;
+            encoder.write_all(expected)?;
+            encoder.finish()?;
+
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                kind,
+                &crate::Flags {
+                    quiet: true,
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+
+            assert_eq!(expected, output_stream, "{kind}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_counts_cover_only_the_stream() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+        let stream_len = compressed_stream.len() as u64;
+        compressed_stream.extend_from_slice(b"trailing garbage");
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                ignore_trailing_garbage: true,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!((0, 0), (ctx.input_bytes(), ctx.output_bytes()));
+        ctx.translate_stream()?;
+
+        assert_eq!(stream_len, ctx.input_bytes());
+        assert_eq!(expected.len() as u64, ctx.output_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursive_decompresses_every_layer() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let gzip = |data: &[u8]| -> Result<Vec<u8>> {
+            let mut compressed_stream = Vec::new();
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(data)?;
+            encoder.finish()?;
+            Ok(compressed_stream)
+        };
+        let gzip_once = gzip(expected.as_bytes())?;
+        let gzip_twice = gzip(&gzip_once)?;
+        let xz_gzip = gzip(&liblzma::encode_all(expected.as_bytes(), XZ_LEVEL)?)?;
+
+        let decompress = |compressed_stream: &[u8], recursive, max_depth| {
+            let mut input_stream = compressed_stream;
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &crate::Flags {
+                    recursive,
+                    max_depth,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream()?;
+            Ok::<_, DecompressError>(output_stream)
+        };
+        for compressed_stream in [&gzip_once, &gzip_twice, &xz_gzip] {
+            assert_eq!(
+                expected.as_bytes(),
+                decompress(compressed_stream, true, None)?
+            );
+        }
+        // one layer at a time without it
+        assert_eq!(gzip_once, decompress(&gzip_twice, false, None)?);
+
+        let gzip_thrice = gzip(&gzip_twice)?;
+        assert_eq!(
+            expected.as_bytes(),
+            decompress(&gzip_thrice, true, Some(3))?
+        );
+        assert!(matches!(
+            decompress(&gzip_thrice, true, Some(2)),
+            Err(DecompressError::TooDeep { depth: 2 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_types_chain() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut compressed_stream = Vec::new();
+        let mut encoder =
+            flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::default());
+        encoder.write_all(expected.as_bytes())?;
+        encoder.finish()?;
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                output_type: vec![CompressionType::Zstd, CompressionType::Xz],
+                ..Default::default()
+            },
+        )?
+        .translate_stream()?;
+
+        // xz on the outside, zstd inside it
+        let zstd_stream = liblzma::decode_all(output_stream.as_slice())?;
+        assert_eq!(
+            expected.as_bytes(),
+            zstd::decode_all(zstd_stream.as_slice())?
+        );
+
+        for output_type in [
+            vec![CompressionType::Zstd, CompressionType::None],
+            vec![CompressionType::Zstd, CompressionType::Zip],
+        ] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let result = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &crate::Flags {
+                    output_type,
+                    ..Default::default()
+                },
+            );
+            assert!(matches!(result, Err(DecompressError::UnsupportedOutput(_))));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_output_type_copies_the_input() -> Result<()> {
+        let expected = "this is a test\n".repeat(100);
+        // level 1 and a name, neither of which recompressing would reproduce
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::GzBuilder::new()
+                .filename("notes.txt")
+                .write(&mut compressed_stream, flate2::Compression::fast());
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        for recompress in [false, true] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![CompressionType::Gzip],
+                    recompress,
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+            drop(ctx);
+
+            if recompress {
+                assert_ne!(compressed_stream, output_stream);
+                let mut decompressed = String::new();
+                flate2::read::GzDecoder::new(output_stream.as_slice())
+                    .read_to_string(&mut decompressed)?;
+                assert_eq!(expected, decompressed);
+            } else {
+                assert_eq!(compressed_stream, output_stream);
+            }
+        }
+
+        Ok(())
+    }
+
+    // a copy never sees the decompressed data, so it can't be what's capped
+    #[test]
+    fn test_max_output_applies_to_the_same_output_type() -> Result<()> {
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::best());
+            encoder.write_all(&[0; 1024 * 1024])?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut io::sink(),
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Gzip],
+                max_output: Some(1024),
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+        assert!(
+            matches!(
+                result,
+                Err(DecompressError::OutputLimitExceeded { limit: 1024 })
+            ),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_garbage_is_dropped_in_the_same_output_type() -> Result<()> {
+        let expected = b"this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::best());
+            encoder.write_all(expected)?;
+        }
+        compressed_stream.extend_from_slice(b"GARBAGE");
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream = Vec::new();
+        Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &crate::Flags {
+                quiet: true,
+                output_type: vec![CompressionType::Gzip],
+                ignore_trailing_garbage: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream()?;
+        assert!(!output_stream.ends_with(b"GARBAGE"));
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(output_stream.as_slice()).read_to_end(&mut decompressed)?;
+        assert_eq!(expected.as_slice(), decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_and_max_bytes_slice_the_output() -> Result<()> {
+        let expected: Vec<u8> = (0..200_000u32).flat_map(|n| n.to_le_bytes()).collect();
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(&expected)?;
+        }
+        // never decoded when the output stops early
+        compressed_stream.extend_from_slice(b"trailing garbage");
+
+        for (skip_bytes, max_bytes, range) in [
+            (Some(123_457), None, 123_457..expected.len()),
+            (None, Some(65_537), 0..65_537),
+            (Some(300_001), Some(70_000), 300_001..370_001),
+            (Some(799_990), Some(70_000), 799_990..expected.len()),
+            (Some(1_000_000), None, expected.len()..expected.len()),
+        ] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &crate::Flags {
+                    quiet: true,
+                    skip_bytes,
+                    max_bytes,
+                    ..Default::default()
+                },
+            )?;
+            let result = ctx.translate_stream();
+            drop(ctx);
+
+            // reading to the end reaches the garbage, which gzip takes for a
+            // broken second member
+            if max_bytes.is_some_and(|max| range.len() as u64 == max) {
+                result?;
+            } else {
+                assert!(result.is_err(), "{skip_bytes:?} {max_bytes:?}");
+            }
+            assert!(
+                expected[range] == output_stream,
+                "{skip_bytes:?} {max_bytes:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_unwrap_round_trips_every_codec() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        for &payload in CompressionType::value_variants() {
+            if payload.is_input_only() {
+                continue;
+            }
+
+            let mut input_stream = expected.as_bytes();
+            let mut wrapped: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut wrapped,
+                CompressionType::None,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![payload],
+                    wrap: true,
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+            drop(ctx);
+            assert!(wrapped.starts_with(WRAP_MAGIC), "{payload}");
+
+            let flags = crate::Flags {
+                quiet: true,
+                unwrap: true,
+                ..Default::default()
+            };
+            let mut input_stream = wrapped.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &flags,
+            )?;
+            ctx.translate_stream()?;
+            assert_eq!(
+                Some((CompressionType::None, payload)),
+                ctx.unwrapped_types()
+            );
+            drop(ctx);
+            assert!(expected.as_bytes() == output_stream, "{payload}");
+
+            // a length that doesn't match what the payload decompresses to
+            let len = wrapped.len();
+            wrapped[len - WRAP_TRAILER_SIZE] ^= 1;
+            let mut input_stream = wrapped.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let result = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &flags,
+            )?
+            .translate_stream();
+            assert!(
+                matches!(result, Err(DecompressError::InvalidWrapper(_))),
+                "{payload}: {result:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_output_stops_decompression_bombs() -> Result<()> {
+        let expected = vec![0; 16 * 1024 * 1024];
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::best());
+            encoder.write_all(&expected)?;
+        }
+        assert!(compressed_stream.len() < 64 * 1024);
+
+        for (max_output, ok) in [(1024 * 1024 + 1, false), (16 * 1024 * 1024, true)] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let result = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Gzip,
+                &crate::Flags {
+                    quiet: true,
+                    decompress_only: true,
+                    max_output: Some(max_output),
+                    ..Default::default()
+                },
+            )?
+            .translate_stream();
+
+            if ok {
+                result?;
+            } else {
+                assert!(
+                    matches!(result, Err(DecompressError::OutputLimitExceeded { limit }) if limit == max_output),
+                    "{result:?}"
+                );
+            }
+            // everything up to the cap is still written
+            assert_eq!(max_output as usize, output_stream.len());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_entries_are_extracted() -> Result<()> {
+        use zip::write::SimpleFileOptions;
+        use zip::CompressionMethod;
+
+        let expected = "this is a test\n".repeat(100);
+        let zip_with = |files: &[(&str, CompressionMethod)]| -> Result<Vec<u8>> {
+            let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+            writer.add_directory("dir/", SimpleFileOptions::default())?;
+            for (name, method) in files {
+                writer.start_file(
+                    *name,
+                    SimpleFileOptions::default().compression_method(*method),
+                )?;
+                writer.write_all(format!("{name}: {expected}").as_bytes())?;
+            }
+            Ok(writer.finish()?.into_inner())
+        };
+        let extract = |archive: &[u8], entry: Option<&str>| -> Result<Vec<u8>, DecompressError> {
+            let flags = crate::Flags {
+                quiet: true,
+                entry: entry.map(Into::into),
+                ..Default::default()
+            };
+            let mut input_stream = archive;
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::Zip, kind);
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+                .translate_stream()?;
+            Ok(output_stream)
+        };
+
+        // the directory doesn't count as a second entry
+        for method in [
+            CompressionMethod::Stored,
+            CompressionMethod::Deflated,
+            CompressionMethod::Bzip2,
+            CompressionMethod::Zstd,
+        ] {
+            let archive = zip_with(&[("dir/notes.txt", method)])?;
+            assert_eq!(
+                format!("dir/notes.txt: {expected}").as_bytes(),
+                extract(&archive, None)?,
+                "{method}"
+            );
+        }
+
+        let archive = zip_with(&[
+            ("a.txt", CompressionMethod::Deflated),
+            ("b.txt", CompressionMethod::Stored),
+        ])?;
+        assert!(matches!(
+            extract(&archive, None),
+            Err(DecompressError::ZipEntryNeeded { entries }) if entries == "a.txt, b.txt"
+        ));
+        assert_eq!(
+            format!("b.txt: {expected}").as_bytes(),
+            extract(&archive, Some("b.txt"))?
+        );
+        assert!(matches!(
+            extract(&archive, Some("c.txt")),
+            Err(DecompressError::ZipEntryNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    // a zip64 size near u64::MAX mustn't wrap around to look in bounds
+    #[test]
+    fn test_zip_entry_sizes_are_checked() -> Result<()> {
+        use zip::write::SimpleFileOptions;
+
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        writer.start_file(
+            "a.txt",
+            SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored)
+                .large_file(true),
+        )?;
+        writer.write_all(b"this is a test")?;
+        let mut archive = writer.finish()?.into_inner();
+        // the central directory's zip64 field, after the name, holds the
+        // uncompressed size and then the compressed one
+        let directory = archive
+            .windows(4)
+            .position(|window| window == b"PK\x01\x02")
+            .expect("the archive has a central directory");
+        let compressed_size = directory + 46 + "a.txt".len() + 4 + 8;
+        archive[compressed_size..compressed_size + 8]
+            .copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+
+        let result = Context::new_from_stream(
+            &mut archive.as_slice(),
+            &mut io::sink(),
+            CompressionType::Zip,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+        assert!(
+            matches!(
+                result,
+                Err(DecompressError::CorruptArchive {
+                    format: CompressionType::Zip,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_7z_entries_are_extracted() -> Result<()> {
+        let extract = |archive: &[u8], entry: Option<&str>| {
+            let flags = crate::Flags {
+                quiet: true,
+                entry: entry.map(Into::into),
+                ..Default::default()
+            };
+            let (kind, _) = detect_stream_characteristics(archive, &flags)?;
+            assert_eq!(CompressionType::SevenZ, kind);
+            transcode_bytes(archive, CompressionType::None, &flags)
+        };
+        let expected = include_bytes!("../fixtures/lzw.txt");
+        let single = include_bytes!("../fixtures/lzw.txt.7z");
+        assert_eq!(expected.as_slice(), extract(single, None)?);
+
+        // the directory doesn't count as a file, the empty one does
+        let several = include_bytes!("../fixtures/several.7z");
+        assert!(matches!(
+            extract(several, None),
+            Err(DecompressError::SevenZEntryNeeded { entries })
+                if entries == "lzw.txt, legacy-v07.txt, empty.txt"
+        ));
+        assert_eq!(expected.as_slice(), extract(several, Some("lzw.txt"))?);
+        assert_eq!(
+            include_bytes!("../fixtures/legacy-v07.txt").as_slice(),
+            extract(several, Some("legacy-v07.txt"))?
+        );
+        assert!(extract(several, Some("empty.txt"))?.is_empty());
+        assert!(matches!(
+            extract(several, Some("dir")),
+            Err(DecompressError::SevenZEntryNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_7z_entries_are_listed() -> Result<()> {
+        let list = |archive: &[u8]| -> Result<String> {
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut &archive[..],
+                &mut output_stream,
+                CompressionType::SevenZ,
+                &crate::Flags {
+                    quiet: true,
+                    list_entries: true,
+                    ..Default::default()
+                },
+            )?
+            .list_entries()?;
+            Ok(String::from_utf8(output_stream)?)
+        };
+
+        // the directory isn't listed, the empty file is
+        assert_eq!(
+            "lzw.txt\nlegacy-v07.txt\nempty.txt\n",
+            list(include_bytes!("../fixtures/several.7z"))?
+        );
+        assert_eq!("lzw.txt\n", list(include_bytes!("../fixtures/lzw.txt.7z"))?);
+
+        Ok(())
+    }
+
+    // a 7z entry is decoded into memory, so the cap has to apply there
+    #[test]
+    fn test_max_output_caps_7z_entries() -> Result<()> {
+        let archive = include_bytes!("../fixtures/lzw.txt.7z");
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = Context::new_from_stream(
+            &mut archive.as_slice(),
+            &mut output_stream,
+            CompressionType::SevenZ,
+            &crate::Flags {
+                quiet: true,
+                max_output: Some(100),
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+
+        assert!(
+            matches!(
+                result,
+                Err(DecompressError::OutputLimitExceeded { limit: 100 })
+            ),
+            "{result:?}"
+        );
+        assert_eq!(100, output_stream.len());
+
+        Ok(())
+    }
+
+    // a codec's kinds of error mean bad input only when the input raised them
+    #[test]
+    fn test_output_errors_arent_corrupt_input() -> Result<()> {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let compressed_stream = zstd::encode_all("this is a test".as_bytes(), 0)?;
+        let mut input_stream = compressed_stream.as_slice();
+        let result = Context::new_from_stream(
+            &mut input_stream,
+            &mut FailingWriter,
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                ..Default::default()
+            },
+        )?
+        .translate_stream();
+        assert!(
+            matches!(&result, Err(DecompressError::Io(e)) if e.to_string() == "disk on fire"),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    // what `--test` relies on: decoding to nowhere still checks everything
+    #[test]
+    fn test_corrupt_input_fails_even_into_a_sink() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let decode = |compressed_stream: &[u8], input_type| -> Result<(), DecompressError> {
+            let flags = crate::Flags {
+                input_type,
+                ..Default::default()
+            };
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(compressed_stream, &flags)?;
+            // like `--test`, input that isn't recognised at all fails too,
+            // e.g. brotli cut too short for its trial decode
+            if kind == CompressionType::None {
+                return Err(DecompressError::UnknownFormat);
+            }
+            let mut output_stream = io::sink();
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+                .translate_stream()
+        };
+
+        // compress has no end marker or checksum, so a cut-off .Z looks
+        // like a shorter one
+        decode(include_bytes!("../fixtures/lzw.txt.Z"), None)?;
+        for &kind in CompressionType::value_variants() {
+            if kind == CompressionType::None || kind.is_input_only() {
+                continue;
+            }
+            let compressed_stream = transcode_bytes(expected.as_bytes(), kind, &Flags::default())?;
+            // raw deflate can't be detected without a hint
+            let hint = (kind == CompressionType::Deflate).then_some(kind);
+            decode(&compressed_stream, hint).map_err(|e| eyre!("{kind}: {e}"))?;
+            let truncated = &compressed_stream[..compressed_stream.len() / 2];
+            assert!(decode(truncated, hint).is_err(), "{kind}");
+        }
+
+        let result = decode(include_bytes!("../fixtures/crc-mismatch.txt.gz"), None);
+        assert!(
+            matches!(
+                result,
+                Err(DecompressError::Corrupt {
+                    format: CompressionType::Gzip,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ar_members_are_extracted() -> Result<()> {
+        let expected = "this is a test\n".repeat(100);
+        let gzip = transcode_bytes(
+            expected.as_bytes(),
+            CompressionType::Gzip,
+            &Flags::default(),
+        )?;
+        let xz = transcode_bytes(expected.as_bytes(), CompressionType::Xz, &Flags::default())?;
+        // laid out like a .deb
+        let mut builder = ar::Builder::new(Vec::new());
+        for (name, data) in [
+            ("debian-binary", b"2.0\n".as_slice()),
+            ("control.tar.gz", &gzip),
+            ("data.tar.xz", &xz),
+        ] {
+            let header = ar::Header::new(name.as_bytes().to_vec(), data.len() as u64);
+            builder.append(&header, data)?;
+        }
+        let archive = builder.into_inner()?;
+
+        let extract = |entry: Option<&str>| {
+            let flags = crate::Flags {
+                quiet: true,
+                entry: entry.map(Into::into),
+                ..Default::default()
+            };
+            let (kind, _) = detect_stream_characteristics(archive.as_slice(), &flags)?;
+            assert_eq!(CompressionType::Ar, kind);
+            transcode_bytes(&archive, CompressionType::None, &flags)
+        };
+        // each member is detected on its own
+        assert_eq!(b"2.0\n".as_slice(), extract(Some("debian-binary"))?);
+        assert_eq!(expected.as_bytes(), extract(Some("control.tar.gz"))?);
+        assert_eq!(expected.as_bytes(), extract(Some("data.tar.xz"))?);
+        assert!(matches!(
+            extract(None),
+            Err(DecompressError::ArMemberNeeded { members })
+                if members == "debian-binary, control.tar.gz, data.tar.xz"
+        ));
+        assert!(matches!(
+            extract(Some("data.tar.zst")),
+            Err(DecompressError::ArMemberNotFound { .. })
+        ));
+
+        Ok(())
+    }
+
+    // the decoders mostly want `BufRead`, which the context supplies itself
+    #[test]
+    fn test_context_decodes_from_a_plain_reader() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        for &kind in CompressionType::value_variants() {
+            if kind.is_input_only() {
+                continue;
+            }
+            let compressed_stream = transcode_bytes(expected.as_bytes(), kind, &Flags::default())?;
+            let flags = crate::Flags {
+                input_type: Some(kind),
+                ..Default::default()
+            };
+
+            let mut input_stream = TrickleReader(&compressed_stream);
+            let mut output_stream = Vec::new();
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+                .translate_stream()
+                .map_err(|e| eyre!("{kind}: {e}"))?;
+            assert_eq!(expected.as_bytes(), output_stream, "{kind}");
 
-impl<T: Read> Read for NoneDecompressor<T> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
-    }
-}
+            let mut input_stream = TrickleReader(&compressed_stream);
+            let flags = crate::Flags {
+                quiet: true,
+                verify: true,
+                output_type: vec![kind],
+                ..flags
+            };
+            Context::new_from_stream(&mut input_stream, &mut io::sink(), kind, &flags)?
+                .verify_stream()
+                .map_err(|e| eyre!("{kind}: {e}"))?;
+        }
 
-impl<T: Read> Decompressor for NoneDecompressor<T> {
-    fn decompress(&mut self, mut stream: Box<dyn Write>) -> Result<()> {
-        io::copy(&mut self.0, &mut stream)?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::io::Write;
+    // raises the stop flag once `after` bytes have been read, like a Ctrl-C
+    // partway through
+    struct InterruptAfter<'a> {
+        data: &'a [u8],
+        after: usize,
+        stop: Arc<AtomicBool>,
+    }
 
-    use super::*;
-    use color_eyre::Result;
+    impl Read for InterruptAfter<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(4096);
+            let n = self.data.read(&mut buf[..len])?;
+            self.after = self.after.saturating_sub(n);
+            if self.after == 0 {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+            Ok(n)
+        }
+    }
 
     #[test]
-    fn test_none_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
+    fn test_stop_flag_still_finishes_the_output() -> Result<()> {
+        let expected = "this is a test\n".repeat(100_000);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut input_stream = InterruptAfter {
+            data: expected.as_bytes(),
+            after: 100_000,
+            stop: stop.clone(),
+        };
         let mut output_stream: Vec<u8> = Vec::new();
-
-        let mut ctx = Context::new_from_stream(
+        let result = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
             CompressionType::None,
             &crate::Flags {
                 quiet: true,
-                hint: "none".into(),
-                output_type: Some(CompressionType::None),
+                output_type: vec![CompressionType::Xz],
+                ..Default::default()
             },
-        )?;
-
-        ctx.translate_stream()?;
+        )?
+        .with_stop_flag(stop)
+        .translate_stream();
+        assert!(
+            matches!(result, Err(DecompressError::Interrupted)),
+            "{result:?}"
+        );
 
-        assert_eq!(expected.as_bytes(), output_stream);
+        // cut short, but a complete xz stream, trailer and all
+        let mut decompressed = String::new();
+        liblzma::read::XzDecoder::new(output_stream.as_slice())
+            .read_to_string(&mut decompressed)?;
+        assert!(decompressed.len() >= 100_000, "{}", decompressed.len());
+        assert!(decompressed.len() < expected.len());
+        assert!(expected.starts_with(&decompressed));
 
         Ok(())
     }
 
+    // plain input is copied as is by default, e.g. `tail -f log | c`
     #[test]
-    fn test_zstd_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
+    fn test_stop_flag_ends_a_passthrough_copy() -> Result<()> {
+        let expected = "this is a test\n".repeat(100_000);
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut input_stream = InterruptAfter {
+            data: expected.as_bytes(),
+            after: 100_000,
+            stop: stop.clone(),
+        };
         let mut output_stream: Vec<u8> = Vec::new();
-
-        let mut ctx = Context::new_from_stream(
+        let result = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
             CompressionType::None,
             &crate::Flags {
                 quiet: true,
-                hint: "zstd".into(),
-                output_type: Some(CompressionType::Zstd),
+                ..Default::default()
             },
-        )?;
+        )?
+        .with_stop_flag(stop)
+        .translate_stream();
+        assert!(
+            matches!(result, Err(DecompressError::Interrupted)),
+            "{result:?}"
+        );
+        assert!(output_stream.len() >= 100_000, "{}", output_stream.len());
+        assert!(output_stream.len() < expected.len());
+        assert!(expected.as_bytes().starts_with(&output_stream));
 
-        ctx.translate_stream()?;
+        Ok(())
+    }
 
-        let mut compressed_stream: Vec<u8> = Vec::new();
-        {
-            let mut encoder = zstd::Encoder::new(&mut compressed_stream, ZSTD_LEVEL)?.auto_finish();
-            encoder.write_all(expected.as_bytes())?;
+    // takes the first few bytes, then fails like a full disk would
+    struct FullDisk(usize);
+
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::ErrorKind::StorageFull.into());
+            }
+            let n = buf.len().min(self.0);
+            self.0 -= n;
+            Ok(n)
         }
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_truncated_output_is_an_error() -> Result<()> {
+        for &kind in CompressionType::value_variants() {
+            if kind.is_input_only() || kind == CompressionType::None {
+                continue;
+            }
+            // small enough that most encoders only write anything when finishing
+            let mut input_stream: &[u8] = b"this is a test";
+            let mut output_stream = FullDisk(8);
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::None,
+                &crate::Flags {
+                    quiet: true,
+                    output_type: vec![kind],
+                    ..Default::default()
+                },
+            )?;
+
+            match ctx.translate_stream() {
+                Err(DecompressError::Io(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::StorageFull, "{kind}")
+                }
+                result => panic!("{kind}: expected a write error, got {result:?}"),
+            }
+        }
 
         Ok(())
     }
 
     #[test]
-    fn test_brotli_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
-        let mut output_stream: Vec<u8> = Vec::new();
+    fn test_flush_every_streams_output_progressively() -> Result<()> {
+        let expected: Vec<u8> = (0..64 * 1024u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
 
+        let mut input_stream = expected.as_slice();
+        let mut output_stream = FlushRecorder::default();
         let mut ctx = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
             CompressionType::None,
             &crate::Flags {
                 quiet: true,
-                hint: "brotli".into(),
-                output_type: Some(CompressionType::Brotli),
+                output_type: vec![CompressionType::Gzip],
+                flush_every: Some(8 * 1024),
+                ..Default::default()
             },
         )?;
-
         ctx.translate_stream()?;
+        drop(ctx);
+
+        // compressed bytes reached the writer long before the end of the input
+        let first_flush = output_stream.flushed_at[0];
+        assert!(output_stream.flushed_at.len() >= 4);
+        assert!(first_flush > 0 && first_flush < output_stream.data.len());
+
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        flate2::read::GzDecoder::new(output_stream.data.as_slice())
+            .read_to_end(&mut decompressed_stream)?;
+        assert_eq!(expected, decompressed_stream);
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_members_are_listed() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut archive);
+            for name in ["a.txt", "dir/b.txt"] {
+                let mut header = tar::Header::new_ustar();
+                header.set_size(14);
+                header.set_cksum();
+                builder.append_data(&mut header, name, "this is a test".as_bytes())?;
+            }
+            builder.finish()?;
+        }
         let mut compressed_stream: Vec<u8> = Vec::new();
         {
-            let mut encoder = brotli::CompressorWriter::new(
-                &mut compressed_stream,
-                BROTLI_BUFFER_SIZE,
-                BROTLI_Q,
-                BROTLI_LGWIN,
-            );
-            encoder.write_all(expected.as_bytes())?;
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed_stream, flate2::Compression::fast());
+            encoder.write_all(&archive)?;
         }
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
+        let flags = crate::Flags {
+            quiet: true,
+            tar_list: true,
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &flags,
+        )?;
+        ctx.list_tar_members()?;
+        assert_eq!(b"a.txt\ndir/b.txt\n".as_slice(), output_stream);
+
+        let mut input_stream: &[u8] = b"this is a test";
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::None,
+            &flags,
+        )?;
+        assert!(matches!(
+            ctx.list_tar_members(),
+            Err(DecompressError::NotTar { .. })
+        ));
 
         Ok(())
     }
 
     #[test]
-    fn test_gzip_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
-        let mut output_stream: Vec<u8> = Vec::new();
+    fn test_empty_input_is_a_no_op() -> Result<()> {
+        for flags in [
+            crate::Flags {
+                quiet: true,
+                hint: "brotli".into(),
+                ..Default::default()
+            },
+            crate::Flags {
+                quiet: true,
+                input_type: Some(CompressionType::Gzip),
+                ..Default::default()
+            },
+        ] {
+            let mut input_stream: &[u8] = b"";
+            let (kind, mut input_stream) =
+                detect_stream_characteristics(&mut input_stream, &flags)?;
+            assert_eq!(CompressionType::None, kind);
+            assert!(input_stream.peeked().is_empty());
+
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                kind,
+                &crate::Flags {
+                    input_type: None,
+                    ..flags
+                },
+            )?;
+            ctx.translate_stream()?;
+            assert!(output_stream.is_empty());
+        }
 
+        // recompressing nothing still makes a valid, empty stream
+        let mut input_stream: &[u8] = b"";
+        let mut output_stream: Vec<u8> = Vec::new();
         let mut ctx = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
             CompressionType::None,
             &crate::Flags {
                 quiet: true,
-                hint: "gzip".into(),
-                output_type: Some(CompressionType::Gzip),
+                output_type: vec![CompressionType::Gzip],
+                ..Default::default()
             },
         )?;
-
         ctx.translate_stream()?;
+        drop(ctx);
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        flate2::read::GzDecoder::new(output_stream.as_slice())
+            .read_to_end(&mut decompressed_stream)?;
+        assert!(decompressed_stream.is_empty());
 
-        let mut compressed_stream: Vec<u8> = Vec::new();
-        {
-            let encoder = flate2::write::GzEncoder::new(
-                &mut compressed_stream,
-                flate2::Compression::default(),
-            );
-            let mut compressor = GzipCompressor(encoder);
-            compressor.compress(Box::new(expected.as_bytes()))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_garbage_is_an_error_unless_ignored() -> Result<()> {
+        let expected = b"this is a test";
+        let mut streams = Vec::new();
+        for &kind in CompressionType::value_variants() {
+            if kind.is_input_only() || kind == CompressionType::None {
+                continue;
+            }
+            let mut compressed_stream = Vec::new();
+            let mut encoder = compressor(kind, &mut compressed_stream, &EncoderOptions::default())?;
+            encoder.write_all(expected)?;
+            encoder.finish()?;
+            streams.push((kind, compressed_stream, expected.to_vec()));
         }
+        let lzw = include_bytes!("../fixtures/lzw.txt").to_vec();
+        streams.push((
+            CompressionType::Lzip,
+            include_bytes!("../fixtures/lzip.txt.lz").to_vec(),
+            b"this is a test\n".repeat(1000),
+        ));
+        streams.push((
+            CompressionType::LzwCompress,
+            include_bytes!("../fixtures/lzw.txt.Z").to_vec(),
+            lzw.clone(),
+        ));
+        streams.push((
+            CompressionType::SevenZ,
+            include_bytes!("../fixtures/lzw.txt.7z").to_vec(),
+            lzw,
+        ));
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
+        for (kind, mut compressed_stream, expected) in streams {
+            compressed_stream.extend_from_slice(b"trailing garbage");
+            for ignore_trailing_garbage in [false, true] {
+                let mut input_stream = compressed_stream.as_slice();
+                let mut output_stream: Vec<u8> = Vec::new();
+                let result = Context::new_from_stream(
+                    &mut input_stream,
+                    &mut output_stream,
+                    kind,
+                    &crate::Flags {
+                        quiet: true,
+                        ignore_trailing_garbage,
+                        ..Default::default()
+                    },
+                )?
+                .translate_stream();
+
+                // with no end marker, the garbage is decoded as more stream
+                let no_end = matches!(kind, CompressionType::LzwCompress | CompressionType::Snappy);
+                match (ignore_trailing_garbage, no_end) {
+                    (true, true) => assert!(
+                        matches!(result, Err(DecompressError::UnsupportedInput(_))),
+                        "{kind}: {result:?}"
+                    ),
+                    (true, false) => {
+                        result?;
+                        assert_eq!(expected, output_stream, "{kind}");
+                    }
+                    (false, _) => assert!(result.is_err(), "{kind} accepted trailing garbage"),
+                }
+            }
+        }
 
         Ok(())
     }
 
+    // a second stream isn't garbage, as pbzip2 writes one per block
     #[test]
-    fn test_deflate_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
+    fn test_concatenated_streams_are_not_trailing_data() -> Result<()> {
+        for kind in [
+            CompressionType::Bzip2,
+            CompressionType::Gzip,
+            CompressionType::Xz,
+        ] {
+            let mut compressed_stream = Vec::new();
+            for part in ["this is ", "a test"] {
+                let mut encoder =
+                    compressor(kind, &mut compressed_stream, &EncoderOptions::default())?;
+                encoder.write_all(part.as_bytes())?;
+                encoder.finish()?;
+            }
+
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                kind,
+                &crate::Flags {
+                    quiet: true,
+                    ..Default::default()
+                },
+            )?
+            .translate_stream()?;
+            assert_eq!(b"this is a test".as_slice(), output_stream, "{kind}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_zstd_is_detected_and_decompressed() -> Result<()> {
+        // a hand-built v0.7 frame holding a single raw block
+        let compressed_stream = include_bytes!("../fixtures/legacy-v07.txt.zst");
+        let expected = include_bytes!("../fixtures/legacy-v07.txt");
+
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Zstd, kind);
+
         let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
+        ctx.translate_stream()?;
+        assert_eq!(expected.as_slice(), output_stream);
+
+        for magic in [
+            [0xfd, 0x2f, 0xb5, 0x1e],
+            [0x22, 0xb5, 0x2f, 0xfd],
+            [0x24, 0xb5, 0x2f, 0xfd],
+        ] {
+            assert_eq!(
+                CompressionType::Zstd,
+                detect_compression_type(&magic, &flags)
+            );
+        }
+        assert_eq!(
+            CompressionType::None,
+            detect_compression_type(&[0x29, 0xb5, 0x2f, 0xfd], &flags)
+        );
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_zstd_long_window_needs_long() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        // like `zstd --long=28`, streamed so the frame header records the window
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = zstd::Encoder::new(&mut compressed_stream, 3)?;
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(28)?;
+            encoder.write_all(expected.as_bytes())?;
+            encoder.finish()?;
+        }
+
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
         let mut ctx = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
-            CompressionType::None,
+            CompressionType::Zstd,
             &crate::Flags {
                 quiet: true,
-                hint: "deflate".into(),
-                output_type: Some(CompressionType::Deflate),
+                ..Default::default()
             },
         )?;
+        let err = ctx.translate_stream().unwrap_err();
+        assert!(matches!(
+            err,
+            DecompressError::ZstdWindowTooLarge { window_log: 27 }
+        ));
+        assert!(err.to_string().contains("--long"));
 
+        let mut input_stream = compressed_stream.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Zstd,
+            &crate::Flags {
+                quiet: true,
+                long: Some(28),
+                ..Default::default()
+            },
+        )?;
         ctx.translate_stream()?;
+        drop(ctx);
+        assert_eq!(expected.as_bytes(), output_stream);
 
-        let mut compressed_stream: Vec<u8> = Vec::new();
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit_rejects_large_windows() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut zstd_stream: Vec<u8> = Vec::new();
         {
-            let encoder = flate2::write::DeflateEncoder::new(
-                &mut compressed_stream,
-                flate2::Compression::default(),
-            );
-            let mut compressor = DeflateCompressor(encoder);
-            compressor.compress(Box::new(expected.as_bytes()))?;
+            let mut encoder = zstd::Encoder::new(&mut zstd_stream, 3)?;
+            encoder.window_log(20)?;
+            encoder.write_all(expected.as_bytes())?;
+            encoder.finish()?;
+        }
+        // level 6 has an 8MiB dictionary
+        let mut xz_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut xz_stream, 6);
+            encoder.write_all(expected.as_bytes())?;
+            encoder.finish()?;
         }
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
+        for (kind, compressed_stream) in [
+            (CompressionType::Zstd, &zstd_stream),
+            (CompressionType::Xz, &xz_stream),
+        ] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                kind,
+                &crate::Flags {
+                    quiet: true,
+                    memory_limit: Some(1 << 16),
+                    ..Default::default()
+                },
+            )?;
+            let err = ctx.translate_stream().unwrap_err();
+            assert!(
+                matches!(err, DecompressError::MemoryLimitExceeded { limit: 65536 }),
+                "{kind:?}: {err}"
+            );
+
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                kind,
+                &crate::Flags {
+                    quiet: true,
+                    memory_limit: Some(1 << 30),
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+            drop(ctx);
+            assert_eq!(expected.as_bytes(), output_stream);
+        }
 
         Ok(())
     }
 
     #[test]
-    fn test_zlib_compression_works() -> Result<()> {
+    fn test_zstd_with_leading_skippable_frame_is_detected() -> Result<()> {
         let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
-        let mut output_stream: Vec<u8> = Vec::new();
+        let metadata = b"some metadata";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        compressed_stream.extend_from_slice(&0x184d2a53u32.to_le_bytes());
+        compressed_stream.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        compressed_stream.extend_from_slice(metadata);
+        compressed_stream.extend_from_slice(&zstd::encode_all(expected.as_bytes(), ZSTD_LEVEL)?);
 
-        let mut ctx = Context::new_from_stream(
-            &mut input_stream,
-            &mut output_stream,
-            CompressionType::None,
-            &crate::Flags {
-                quiet: true,
-                hint: "zlib".into(),
-                output_type: Some(CompressionType::Zlib),
-            },
-        )?;
+        let flags = crate::Flags {
+            quiet: true,
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
+        let (kind, mut input_stream) = detect_stream_characteristics(&mut input_stream, &flags)?;
+        assert_eq!(CompressionType::Zstd, kind);
 
+        let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx =
+            Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?;
         ctx.translate_stream()?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_verify_reports_the_first_difference() -> Result<()> {
+        let expected = "this is a test, this is only a test";
         let mut compressed_stream: Vec<u8> = Vec::new();
         {
-            let encoder = flate2::write::ZlibEncoder::new(
+            let mut encoder = flate2::write::GzEncoder::new(
                 &mut compressed_stream,
                 flate2::Compression::default(),
             );
-            let mut compressor = ZlibCompressor(encoder);
-            compressor.compress(Box::new(expected.as_bytes()))?;
+            encoder.write_all(expected.as_bytes())?;
         }
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_xz_compression_works() -> Result<()> {
-        let expected = "this is a test";
-        let mut input_stream = expected.as_bytes();
+        let flags = crate::Flags {
+            quiet: true,
+            output_type: vec![CompressionType::Gzip],
+            ..Default::default()
+        };
+        let mut input_stream = compressed_stream.as_slice();
         let mut output_stream: Vec<u8> = Vec::new();
+        let mut ctx = Context::new_from_stream(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Gzip,
+            &flags,
+        )?;
+        ctx.verify_stream()?;
+        assert!(output_stream.is_empty());
 
+        // same contents, but the OS byte in the header differs
+        let mut foreign_stream = compressed_stream.clone();
+        foreign_stream[9] = 3;
+        let mut input_stream = foreign_stream.as_slice();
         let mut ctx = Context::new_from_stream(
             &mut input_stream,
             &mut output_stream,
-            CompressionType::None,
-            &crate::Flags {
-                quiet: true,
-                hint: "xz".into(),
-                output_type: Some(CompressionType::Xz),
-            },
+            CompressionType::Gzip,
+            &flags,
         )?;
+        let result = ctx.verify_stream();
+        assert!(
+            matches!(
+                result,
+                Err(DecompressError::NotReproduced { offset: 9, .. })
+            ),
+            "{result:?}"
+        );
 
-        ctx.translate_stream()?;
+        Ok(())
+    }
 
+    #[test]
+    fn test_multithreaded_xz_decoding_works() -> Result<()> {
+        let expected: Vec<u8> = "this is a test\n".repeat(64 * 1024).into_bytes();
+        let stream = liblzma::stream::MtStreamBuilder::new()
+            .threads(4)
+            .block_size(64 * 1024)
+            .preset(XZ_LEVEL)
+            .encoder()?;
         let mut compressed_stream: Vec<u8> = Vec::new();
         {
-            let mut encoder = xz2::write::XzEncoder::new(&mut compressed_stream, XZ_LEVEL);
-            encoder.write_all(expected.as_bytes())?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut compressed_stream, stream);
+            encoder.write_all(&expected)?;
         }
 
-        assert!(!compressed_stream.is_empty());
-        assert_eq!(compressed_stream, output_stream);
-        assert_ne!(expected.as_bytes(), output_stream);
+        // a block-split stream, and a single-block one that can't be split up
+        let single_block_stream = liblzma::encode_all(expected.as_slice(), XZ_LEVEL)?;
+        for compressed_stream in [compressed_stream, single_block_stream] {
+            let mut input_stream = compressed_stream.as_slice();
+            let mut output_stream: Vec<u8> = Vec::new();
+            let mut ctx = Context::new_from_stream(
+                &mut input_stream,
+                &mut output_stream,
+                CompressionType::Xz,
+                &crate::Flags {
+                    quiet: true,
+                    threads: Some(4),
+                    ..Default::default()
+                },
+            )?;
+            ctx.translate_stream()?;
+            assert_eq!(expected, output_stream);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_size_is_read_from_isize() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(expected.as_bytes())?;
+        let compressed_stream = encoder.finish()?;
+        assert_eq!(
+            Some(expected.len() as u64),
+            gzip_uncompressed_size(&mut io::Cursor::new(&compressed_stream))?
+        );
+        assert_eq!(
+            None,
+            gzip_uncompressed_size(&mut io::Cursor::new(expected.as_bytes()))?
+        );
+        assert_eq!(
+            None,
+            gzip_uncompressed_size(&mut io::Cursor::new(&compressed_stream[..10]))?
+        );
+
+        // nothing is guessed from a prefix
+        assert_eq!(
+            None,
+            uncompressed_size_hint(CompressionType::Gzip, &compressed_stream)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xz_size_is_read_from_the_index() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let compressed_stream = liblzma::encode_all(expected.as_bytes(), XZ_LEVEL)?;
+        assert_eq!(
+            Some(expected.len() as u64),
+            xz_uncompressed_size(&mut io::Cursor::new(&compressed_stream))?
+        );
+
+        // concatenated streams with stream padding in between and at the end
+        let mut concatenated_stream = compressed_stream.clone();
+        concatenated_stream.extend_from_slice(&[0; 8]);
+        concatenated_stream.extend_from_slice(&liblzma::encode_all(b"more".as_slice(), 0)?);
+        concatenated_stream.extend_from_slice(&[0; 4]);
+        assert_eq!(
+            Some(expected.len() as u64 + 4),
+            xz_uncompressed_size(&mut io::Cursor::new(&concatenated_stream))?
+        );
+
+        let truncated_stream = &compressed_stream[..compressed_stream.len() - 1];
+        assert_eq!(
+            None,
+            xz_uncompressed_size(&mut io::Cursor::new(truncated_stream))?
+        );
+        assert_eq!(
+            None,
+            xz_uncompressed_size(&mut io::Cursor::new(expected.as_bytes()))?
+        );
+
+        // nothing is guessed from a prefix
+        assert_eq!(
+            None,
+            uncompressed_size_hint(
+                CompressionType::Xz,
+                &compressed_stream[..DETECT_BUFFER_SIZE]
+            )
+        );
 
         Ok(())
     }
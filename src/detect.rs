@@ -0,0 +1,872 @@
+use std::io::{self, Read};
+use std::ops::RangeInclusive;
+
+use brotli::enc::StandardAlloc;
+use brotli::BrotliResult;
+use clap::ValueEnum;
+
+use crate::ctx::{BROTLI_BUFFER_SIZE, TAR_BLOCK_SIZE};
+use crate::error::DecompressError;
+use crate::peek::PeekReader;
+use crate::{registry, Flags};
+
+pub(crate) const BROTLI_TRIAL_SIZE: usize = 64 * 1024;
+const BROTLI_TRIAL_OUTPUT_LIMIT: usize = 16 * 1024 * 1024;
+
+// large enough for the longest zstd frame header
+pub(crate) const DETECT_BUFFER_SIZE: usize = 18;
+
+// what `zstd --train` writes at the start of a dictionary
+const ZSTD_DICTIONARY_MAGIC: [u8; 4] = [0x37, 0xa4, 0x30, 0xec];
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+/// Detects the compression type from the start of `stream`, returning it along
+/// with the stream, the bytes read to do so put back in front. An empty input
+/// is always `None` with an empty prefix, whatever the hint or forced type,
+/// since there's nothing to decode. A UTF-8 BOM in front of a magic is dropped,
+/// as are the first `--strip-leading` bytes, so neither is in the prefix.
+pub fn detect_stream_characteristics<R: Read>(
+    mut stream: R,
+    flags: &Flags,
+) -> Result<(CompressionType, PeekReader<R>), DecompressError> {
+    let (kind, prefix) = detect_with_peek(&mut stream, flags, DETECT_BUFFER_SIZE)?;
+    Ok((kind, PeekReader::new(prefix, stream)))
+}
+
+/// How [`detect_with_confidence`] came to its answer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Confidence {
+    /// The type was forced, or its magic matched.
+    Certain,
+    /// Brotli or raw deflate, picked by hint or by a trial decode. Nothing in
+    /// the bytes themselves proves it.
+    Guessed,
+    /// No magic matched, so the input is taken to be uncompressed.
+    NoMatch,
+    /// The input ended `missing` bytes into what could have been the magic of
+    /// `candidate`, at least two bytes in, so it may be a cut-off stream rather
+    /// than plain data.
+    Truncated {
+        candidate: CompressionType,
+        missing: usize,
+    },
+}
+
+/// Like [`detect_stream_characteristics`], but also says how sure it is. Short
+/// reads are retried until the longest magic fits or the input ends, so a pipe
+/// that yields a byte at a time is detected the same as a file.
+pub fn detect_with_confidence<R: Read>(
+    stream: R,
+    flags: &Flags,
+) -> Result<(CompressionType, PeekReader<R>, Confidence), DecompressError> {
+    let (kind, stream) = detect_stream_characteristics(stream, flags)?;
+    let prefix = stream.peeked();
+    let confidence = if flags.forced_input_type().is_some() || kind.matches_magic(prefix) {
+        Confidence::Certain
+    } else if kind != CompressionType::None {
+        Confidence::Guessed
+    } else {
+        // the longest magic the input could have been cut off in. one byte
+        // says too little, since plenty of text starts like some magic
+        CompressionType::value_variants()
+            .iter()
+            .filter(|candidate| {
+                let magic = candidate.magic_bytes();
+                prefix.len() >= 2 && prefix.len() < magic.len() && magic.starts_with(prefix)
+            })
+            .map(|candidate| Confidence::Truncated {
+                candidate: *candidate,
+                missing: candidate.magic_bytes().len() - prefix.len(),
+            })
+            .next()
+            .unwrap_or(Confidence::NoMatch)
+    };
+
+    Ok((kind, stream, confidence))
+}
+
+// never peeks less than the longest header detection looks at, so e.g. lzma's
+// `5d 00 00` isn't cut short into something that matches nothing
+pub(crate) fn detect_with_peek<R: Read>(
+    stream: &mut R,
+    flags: &Flags,
+    peek_len: usize,
+) -> Result<(CompressionType, Vec<u8>), DecompressError> {
+    let peek_len = peek_len
+        .max(DETECT_BUFFER_SIZE)
+        .max(registry::longest_magic());
+    if let Some(strip) = flags.strip_leading {
+        io::copy(&mut stream.by_ref().take(strip), &mut io::sink())?;
+    }
+    let mut buffer = vec![0; peek_len];
+    let mut n = if flags.forced_input_type().is_some() {
+        read_prefix(stream, &mut buffer)?
+    } else {
+        read_detect_prefix(stream, &mut buffer, flags)?
+    };
+    if n == 0 {
+        return Ok((CompressionType::None, Vec::new()));
+    }
+    if let Some(kind) = flags.forced_input_type() {
+        // still hand the prefix back so the decoder sees the whole stream
+        buffer.truncate(n);
+        return Ok((kind, buffer));
+    }
+    let mut kind = detect_compression_type(&buffer[..n], flags);
+
+    // some tools put a UTF-8 BOM in front of whatever they write, hiding the
+    // magic. it's only dropped when one follows, so text keeps its BOM
+    if kind == CompressionType::None && buffer[..n].starts_with(&UTF8_BOM) {
+        buffer.resize(peek_len + UTF8_BOM.len(), 0);
+        if n == peek_len {
+            n += read_prefix(stream, &mut buffer[n..])?;
+        }
+        let behind = detect_compression_type(&buffer[UTF8_BOM.len()..n], flags);
+        if behind != CompressionType::None {
+            buffer.drain(..UTF8_BOM.len());
+            n -= UTF8_BOM.len();
+            kind = behind;
+        }
+    }
+
+    // brotli has no magic, so small inputs that match nothing else get a trial
+    // decode instead. --detect-only reads no further than the magics need, so
+    // there it only tries what's already read, and a longer stream needs a hint.
+    // neither does text, which brotli never looks like, so that a pipe of log
+    // lines isn't held up until 64 KiB of them have come
+    if kind == CompressionType::None {
+        let maybe_brotli = flags.brotli_input_window().is_some() || !looks_like_text(&buffer[..n]);
+        if n == buffer.len() && n < BROTLI_TRIAL_SIZE && !flags.detect_only && maybe_brotli {
+            buffer.resize(BROTLI_TRIAL_SIZE, 0);
+            n += read_prefix(stream, &mut buffer[n..])?;
+        }
+        // a window to match rules out more of what happens to decode
+        let window_matches = flags
+            .brotli_input_window()
+            .is_none_or(|window| brotli_window_bits(&buffer[..n]) == Some(window));
+        if n < BROTLI_TRIAL_SIZE && window_matches && is_brotli(&buffer[..n]) {
+            kind = CompressionType::Brotli;
+        }
+    }
+    buffer.truncate(n);
+
+    Ok((kind, buffer))
+}
+
+/// Whether a prefix that matched no magic reads as text: valid UTF-8, bar a
+/// character cut off at the end, with at most one control character in 20,
+/// not counting whitespace or the escapes of colored logs. Compressed data
+/// hardly ever gets that far, so such input is almost certainly plaintext.
+pub fn looks_like_text(prefix: &[u8]) -> bool {
+    let text = match std::str::from_utf8(prefix) {
+        Ok(text) => text,
+        // the prefix can end partway through a character
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&prefix[..e.valid_up_to()]).expect("checked up to here")
+        }
+        Err(_) => return false,
+    };
+    let (chars, control) = text.chars().fold((0, 0), |(chars, control), c| {
+        let is_control = c.is_control() && !c.is_whitespace() && c != '\x1b';
+        (chars + 1, control + usize::from(is_control))
+    });
+    chars > 0 && control * 20 <= chars
+}
+
+/// Whether a prefix starts with a tar header, whose block carries the `ustar`
+/// magic at offset 257. Nothing compressed does, so it's a tar that was never
+/// compressed, or was already decompressed.
+pub fn looks_like_tar(prefix: &[u8]) -> bool {
+    prefix.len() >= TAR_BLOCK_SIZE && &prefix[257..262] == b"ustar"
+}
+
+/// Whether a prefix starts with a zstd dictionary's magic. Dictionaries aren't
+/// compressed streams, so detection leaves them as `None`, but passing one
+/// through is almost always a mistake.
+pub fn looks_like_zstd_dictionary(prefix: &[u8]) -> bool {
+    prefix.starts_with(&ZSTD_DICTIONARY_MAGIC)
+}
+
+// A single `read` on a slow pipe can come back short, so keep going until the
+// buffer is full or the stream is exhausted.
+pub(crate) fn read_prefix<R: Read>(stream: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+// Like `read_prefix`, but stops early at a line of text that no magic starts
+// with, since no more of it can change what's detected, and a pipe that's gone
+// quiet after it, like `tail -f`, shouldn't keep the line from coming out.
+fn read_detect_prefix<R: Read>(
+    stream: &mut R,
+    buffer: &mut [u8],
+    flags: &Flags,
+) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        let prefix = &buffer[..filled];
+        if prefix.ends_with(b"\n")
+            && looks_like_text(prefix)
+            && detect_compression_type(prefix, flags) == CompressionType::None
+            && !could_be_cut_off_magic(prefix)
+        {
+            break;
+        }
+    }
+
+    Ok(filled)
+}
+
+// whether `prefix` is the start of a magic that the input ended, or a read
+// stopped, partway through
+fn could_be_cut_off_magic(prefix: &[u8]) -> bool {
+    CompressionType::value_variants().iter().any(|kind| {
+        kind.magic_bytes().len() > prefix.len() && kind.magic_bytes().starts_with(prefix)
+    }) || registry::could_be_cut_off(prefix)
+}
+
+// True only for exactly one complete brotli stream. An empty stream can be a
+// single byte, so it also has to decode to something.
+fn is_brotli(buffer: &[u8]) -> bool {
+    let mut state = brotli::BrotliState::new(
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+        StandardAlloc::default(),
+    );
+    let mut output = vec![0; BROTLI_BUFFER_SIZE];
+    let mut available_in = buffer.len();
+    let mut input_offset = 0;
+    let mut total_out = 0;
+    loop {
+        let mut available_out = output.len();
+        let mut output_offset = 0;
+        let result = brotli::BrotliDecompressStream(
+            &mut available_in,
+            &mut input_offset,
+            buffer,
+            &mut available_out,
+            &mut output_offset,
+            &mut output,
+            &mut total_out,
+            &mut state,
+        );
+        match result {
+            // nothing else gets this far without an error, so don't spend
+            // forever inflating a highly compressed input
+            BrotliResult::NeedsMoreOutput if total_out >= BROTLI_TRIAL_OUTPUT_LIMIT => return true,
+            BrotliResult::NeedsMoreOutput => continue,
+            BrotliResult::ResultSuccess => return available_in == 0 && total_out > 0,
+            BrotliResult::NeedsMoreInput | BrotliResult::ResultFailure => return false,
+        }
+    }
+}
+
+// The window a brotli stream declares in its first bits, as a power of two,
+// or None if the prefix is empty or can't start one. The large-window
+// extension spells its size out in the 6 bits after the usual ones.
+pub(crate) fn brotli_window_bits(prefix: &[u8]) -> Option<u32> {
+    let first = *prefix.first()?;
+    if first & 0x01 == 0 {
+        return Some(16);
+    }
+    match (first >> 1) & 0x07 {
+        0 => {}
+        n => return Some(17 + u32::from(n)),
+    }
+    match (first >> 4) & 0x07 {
+        0 => Some(17),
+        1 if first & 0x80 == 0 => {
+            Some(u32::from(prefix.get(1)? & 0x3f)).filter(|bits| (10..=30).contains(bits))
+        }
+        1 => None,
+        m => Some(8 + u32::from(m)),
+    }
+}
+
+// binary data starting with `78 9c` is common enough that the two bytes alone
+// aren't proof, so check what follows them is a plausible deflate stream
+fn is_zlib_header(buffer: &[u8]) -> bool {
+    let [cmf, flg, deflate @ ..] = buffer else {
+        return false;
+    };
+    // FCHECK makes the header a multiple of 31, and FDICT needs a dictionary
+    // we don't have
+    if (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 != 0 || flg & 0x20 != 0 {
+        return false;
+    }
+
+    match deflate {
+        // BTYPE 3 is reserved
+        [block, ..] if (block >> 1) & 0b11 == 0b11 => false,
+        // a stored block's length is followed by its complement
+        [block, len_lo, len_hi, nlen_lo, nlen_hi, ..] if (block >> 1) & 0b11 == 0 => {
+            u16::from_le_bytes([*len_lo, *len_hi]) == !u16::from_le_bytes([*nlen_lo, *nlen_hi])
+        }
+        _ => true,
+    }
+}
+
+pub(crate) fn detect_compression_type(buffer: &[u8], flags: &Flags) -> CompressionType {
+    // no two built-in magics overlap, so the order they're tried in doesn't
+    // matter. custom ones can, so they're skipped here and get what's left
+    if let Some(kind) = CompressionType::value_variants()
+        .iter()
+        .find(|kind| kind.matches_magic(buffer))
+    {
+        *kind
+    } else if CompressionType::Custom.matches_magic(buffer) {
+        CompressionType::Custom
+    } else {
+        // brotli and raw deflate have no header to sniff, so they're only ever
+        // picked by hint. any other hint is already covered by its magic
+        match CompressionType::from_hint(&flags.hint) {
+            Some(kind @ (CompressionType::Brotli | CompressionType::Deflate)) => kind,
+            _ => CompressionType::None,
+        }
+    }
+}
+
+/// The type the hint names, if a magic says the input is something else. The
+/// magic wins over a hint, and only `--force-input-type` overrides it, so the
+/// hint is ignored, but it's likely a mistake worth a word.
+pub fn hint_disagrees(
+    kind: CompressionType,
+    prefix: &[u8],
+    flags: &Flags,
+) -> Option<CompressionType> {
+    if flags.forced_input_type().is_some() || !kind.matches_magic(prefix) {
+        return None;
+    }
+    match (CompressionType::from_hint(&flags.hint)?, kind) {
+        // `identity` says nothing about what the body is, e.g. a .gz download
+        (CompressionType::None, _) => None,
+        // a `deflate` Content-Encoding is often really zlib
+        (CompressionType::Deflate, CompressionType::Zlib) => None,
+        (hinted, kind) if hinted == kind => None,
+        (hinted, _) => Some(hinted),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ValueEnum, strum::Display, serde::Serialize)]
+// the names clap gives them
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionType {
+    None,
+    Ar,
+    Brotli,
+    Bzip2,
+    Deflate,
+    Gzip,
+    Lz4,
+    Lzip,
+    Lzma,
+    Lzma2,
+    LzwCompress,
+    #[value(name = "7z")]
+    #[serde(rename = "7z")]
+    #[strum(serialize = "7z")]
+    SevenZ,
+    Snappy,
+    Xz,
+    Zip,
+    Zlib,
+    Zstd,
+    // whatever was added with `register_decoder`, so it has no name to pass
+    #[value(skip)]
+    Custom,
+}
+
+impl CompressionType {
+    /// The type conventionally named by a file extension, e.g. `gz`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "7z" => Some(CompressionType::SevenZ),
+            "br" => Some(CompressionType::Brotli),
+            "deb" => Some(CompressionType::Ar),
+            "bz2" => Some(CompressionType::Bzip2),
+            "deflate" => Some(CompressionType::Deflate),
+            "gz" => Some(CompressionType::Gzip),
+            "lz" => Some(CompressionType::Lzip),
+            "lz4" => Some(CompressionType::Lz4),
+            "lzma" => Some(CompressionType::Lzma),
+            "sz" => Some(CompressionType::Snappy),
+            "xz" => Some(CompressionType::Xz),
+            "Z" => Some(CompressionType::LzwCompress),
+            "zip" => Some(CompressionType::Zip),
+            "zst" => Some(CompressionType::Zstd),
+            "zz" => Some(CompressionType::Zlib),
+            _ => None,
+        }
+    }
+
+    /// The type named by a hint, in any case: one of the `--force-input-type`
+    /// names, or an HTTP `Content-Encoding` token like `br`. `identity` is
+    /// `None`.
+    pub fn from_hint(hint: &str) -> Option<Self> {
+        match hint.to_ascii_lowercase().as_str() {
+            "br" => Some(CompressionType::Brotli),
+            "x-gzip" => Some(CompressionType::Gzip),
+            "compress" | "x-compress" => Some(CompressionType::LzwCompress),
+            "identity" => Some(CompressionType::None),
+            // HTTP's `deflate` is really zlib, but servers send raw deflate
+            // too. zlib has a magic, so mapping it to raw deflate covers both
+            hint => CompressionType::from_str(hint, true).ok(),
+        }
+    }
+
+    /// The bytes every stream of this type starts with, or nothing for types
+    /// that can't be recognized from fixed first bytes. zlib's header is
+    /// checked rather than matched, so use [`Self::matches_magic`] to tell if
+    /// a buffer starts a stream.
+    pub fn magic_bytes(&self) -> &'static [u8] {
+        match self {
+            CompressionType::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            CompressionType::Gzip => &[0x1f, 0x8b],
+            CompressionType::LzwCompress => &[0x1f, 0x9d],
+            CompressionType::Xz => &[0xfd, 0x37, 0x7a, 0x58, 0x5a],
+            CompressionType::Lzma => &[0x5d, 0x00, 0x00],
+            CompressionType::Lz4 => &[0x04, 0x22, 0x4d, 0x18],
+            CompressionType::Lzip => b"LZIP",
+            CompressionType::Bzip2 => &[0x42, 0x5a, 0x68],
+            CompressionType::Snappy => b"\xff\x06\x00\x00sNaPpY",
+            CompressionType::Zip => b"PK\x03\x04",
+            CompressionType::Ar => b"!<arch>\n",
+            CompressionType::SevenZ => &[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c],
+            CompressionType::None
+            | CompressionType::Brotli
+            | CompressionType::Custom
+            | CompressionType::Deflate
+            | CompressionType::Lzma2
+            | CompressionType::Zlib => &[],
+        }
+    }
+
+    /// Whether `buffer` starts with this type's magic, the same way detection
+    /// decides it.
+    pub fn matches_magic(&self, buffer: &[u8]) -> bool {
+        match self {
+            // or a skippable frame, which libzstd steps over to get to the
+            // real one, or a frame from a pre-1.0 release. v0.1 wrote its
+            // magic big-endian
+            CompressionType::Zstd => {
+                buffer.starts_with(self.magic_bytes())
+                    || matches!(buffer, [0x50..=0x5f, 0x2a, 0x4d, 0x18, ..])
+                    || matches!(buffer, [0x22..=0x27, 0xb5, 0x2f, 0xfd, ..])
+                    || buffer.starts_with(&[0xfd, 0x2f, 0xb5, 0x1e])
+            }
+            // a lone 0x78 is too common in plain text, so check the FLG byte
+            // for one of the levels zlib itself writes
+            CompressionType::Zlib => {
+                matches!(buffer, [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..]) && is_zlib_header(buffer)
+            }
+            CompressionType::Custom => registry::find(buffer).is_some(),
+            _ => !self.magic_bytes().is_empty() && buffer.starts_with(self.magic_bytes()),
+        }
+    }
+
+    pub(crate) fn level_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            CompressionType::Zstd => Some(zstd::compression_level_range()),
+            CompressionType::Bzip2 => Some(1..=9),
+            CompressionType::Deflate
+            | CompressionType::Gzip
+            | CompressionType::Lzma
+            | CompressionType::Xz
+            | CompressionType::Zlib => Some(0..=9),
+            CompressionType::None
+            | CompressionType::Ar
+            | CompressionType::Brotli
+            | CompressionType::Custom
+            | CompressionType::Lz4
+            | CompressionType::Lzip
+            | CompressionType::Lzma2
+            | CompressionType::LzwCompress
+            | CompressionType::SevenZ
+            | CompressionType::Snappy
+            | CompressionType::Zip => None,
+        }
+    }
+
+    pub(crate) fn is_input_only(&self) -> bool {
+        matches!(
+            self,
+            CompressionType::Ar
+                | CompressionType::Custom
+                | CompressionType::Lzip
+                | CompressionType::Lzma2
+                | CompressionType::LzwCompress
+                | CompressionType::SevenZ
+                | CompressionType::Zip
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use color_eyre::Result;
+    use std::io::Write;
+
+    // the CLI reads a stream, while a prefix that's already in memory can be
+    // checked directly, and both have to agree
+    #[test]
+    fn test_stream_and_buffer_detection_agree() -> Result<()> {
+        let gzip: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let deflate: &[u8] = &[0x2b, 0xc9, 0xc8, 0x2c, 0x56, 0x00, 0xa2, 0x44, 0x85];
+        for (input, hint, expected) in [
+            (gzip, "unknown", CompressionType::Gzip),
+            // a hint never overrides a magic
+            (gzip, "deflate", CompressionType::Gzip),
+            (deflate, "unknown", CompressionType::None),
+            (deflate, "deflate", CompressionType::Deflate),
+        ] {
+            let flags = Flags {
+                hint: hint.into(),
+                ..Default::default()
+            };
+            assert_eq!(expected, detect_compression_type(input, &flags), "{hint}");
+
+            let mut stream = input;
+            let (kind, stream) = detect_stream_characteristics(&mut stream, &flags)?;
+            assert_eq!(expected, kind, "{hint}");
+            assert_eq!(input, stream.peeked());
+        }
+
+        Ok(())
+    }
+
+    // a pipe can hand over as little as a byte per read
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_hint_that_disagrees_with_the_magic() {
+        let gzip: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let zlib: &[u8] = &[0x78, 0x9c, 0x2b, 0xc9, 0xc8, 0x2c, 0x56, 0x00, 0xa2, 0x44];
+        let no_magic: &[u8] = &[0x0b, 0x06, 0x80, 0x74, 0x68, 0x69, 0x73, 0x03];
+        for (input, hint, force, expected) in [
+            (gzip, "brotli", None, Some(CompressionType::Brotli)),
+            (gzip, "x-zstd", None, None),
+            (gzip, "zstd", None, Some(CompressionType::Zstd)),
+            (gzip, "gzip", None, None),
+            (gzip, "unknown", None, None),
+            (gzip, "identity", None, None),
+            (zlib, "deflate", None, None),
+            // brotli has no magic to disagree with
+            (no_magic, "gzip", None, None),
+            // forcing a type means the magic was never looked at
+            (gzip, "brotli", Some(CompressionType::Brotli), None),
+        ] {
+            let flags = Flags {
+                hint: hint.into(),
+                input_type: force,
+                ..Default::default()
+            };
+            let kind = flags
+                .forced_input_type()
+                .unwrap_or_else(|| detect_compression_type(input, &flags));
+            assert_eq!(expected, hint_disagrees(kind, input, &flags), "{hint}");
+        }
+        // the magic still wins
+        let flags = Flags {
+            hint: "brotli".into(),
+            ..Default::default()
+        };
+        assert_eq!(CompressionType::Gzip, detect_compression_type(gzip, &flags));
+    }
+
+    #[test]
+    fn test_detection_reads_past_short_reads() -> Result<()> {
+        let gzip: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let snappy: &[u8] = b"\xff\x06\x00\x00sNaPpY\x01\x02";
+        for (input, expected, confidence) in [
+            (gzip, CompressionType::Gzip, Confidence::Certain),
+            (snappy, CompressionType::Snappy, Confidence::Certain),
+            (
+                b"this is a test".as_slice(),
+                CompressionType::None,
+                Confidence::NoMatch,
+            ),
+            (b"".as_slice(), CompressionType::None, Confidence::NoMatch),
+            (&gzip[..1], CompressionType::None, Confidence::NoMatch),
+            (b"B".as_slice(), CompressionType::None, Confidence::NoMatch),
+            (
+                b"BZ".as_slice(),
+                CompressionType::None,
+                Confidence::Truncated {
+                    candidate: CompressionType::Bzip2,
+                    missing: 1,
+                },
+            ),
+            (
+                &snappy[..4],
+                CompressionType::None,
+                Confidence::Truncated {
+                    candidate: CompressionType::Snappy,
+                    missing: 6,
+                },
+            ),
+        ] {
+            let mut stream = OneByteReader(input);
+            let (kind, stream, actual) = detect_with_confidence(&mut stream, &Flags::default())?;
+            assert_eq!(expected, kind, "{input:x?}");
+            assert_eq!(confidence, actual, "{input:x?}");
+            assert_eq!(input, stream.peeked());
+        }
+
+        let flags = Flags {
+            hint: "deflate".into(),
+            ..Default::default()
+        };
+        let mut stream = OneByteReader(b"\x2b\xc9\xc8\x2c\x56\x00\xa2\x44\x85");
+        let (kind, _, confidence) = detect_with_confidence(&mut stream, &flags)?;
+        assert_eq!(
+            (CompressionType::Deflate, Confidence::Guessed),
+            (kind, confidence)
+        );
+
+        Ok(())
+    }
+
+    // text starting with `x` has zlib's first byte, but no zlib header
+    #[test]
+    fn test_zlib_magic_agrees_with_detection() -> Result<()> {
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(b"this is a test")?;
+        let zlib = zlib.finish()?;
+        for (input, expected) in [
+            (zlib.as_slice(), true),
+            (b"xylophone".as_slice(), false),
+            (b"x\x9c but not zlib".as_slice(), false),
+        ] {
+            let flags = Flags::default();
+            assert_eq!(
+                expected,
+                CompressionType::Zlib.matches_magic(input),
+                "{input:x?}"
+            );
+            assert_eq!(
+                expected,
+                detect_compression_type(input, &flags) == CompressionType::Zlib
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_and_binary_are_told_apart() -> Result<()> {
+        let text = "plain text, ünïcödé included\n\tand \x1b[1mcolors\x1b[0m\n".repeat(10);
+        let binary: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for (input, expected) in [
+            (text.as_bytes(), true),
+            // cut partway through the `ü`
+            (&text.as_bytes()[..13], true),
+            (binary.as_slice(), false),
+            // valid UTF-8, but hardly text
+            (&[1, 2, 3, 4, 5, 6, 7, 8], false),
+            (b"", false),
+        ] {
+            let mut stream = input;
+            let (kind, stream, confidence) =
+                detect_with_confidence(&mut stream, &Flags::default())?;
+            assert_eq!(
+                (CompressionType::None, Confidence::NoMatch),
+                (kind, confidence)
+            );
+            assert_eq!(expected, looks_like_text(stream.peeked()), "{input:x?}");
+        }
+
+        Ok(())
+    }
+
+    // a pipe like `tail -f`'s, which has a line to give and then nothing more
+    struct QuietAfterOneLine<'a>(&'a [u8]);
+
+    impl Read for QuietAfterOneLine<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            assert!(
+                !self.0.is_empty(),
+                "read again after the line, which blocks"
+            );
+            let n = buf.len().min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_a_line_of_text_is_not_waited_past() -> Result<()> {
+        for line in ["a log line\n", "B\n", "LZ\n", "ünïcödé\n"] {
+            let mut stream = QuietAfterOneLine(line.as_bytes());
+            let (kind, stream, confidence) =
+                detect_with_confidence(&mut stream, &Flags::default())?;
+            assert_eq!(
+                (CompressionType::None, Confidence::NoMatch),
+                (kind, confidence),
+                "{line}"
+            );
+            assert_eq!(line.as_bytes(), stream.peeked());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_only_reads_no_further_than_the_magics() -> Result<()> {
+        let text = "this is a test\n".repeat(1000);
+        let mut brotli = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut brotli, 4096, 0, 22);
+            encoder.write_all(text.as_bytes())?;
+        }
+        assert!(brotli.len() > DETECT_BUFFER_SIZE);
+        let detect_only = Flags {
+            detect_only: true,
+            ..Default::default()
+        };
+        let hinted = Flags {
+            hint: "br".into(),
+            ..detect_only.clone()
+        };
+        for (input, flags, expected) in [
+            (text.as_bytes(), &Flags::default(), CompressionType::None),
+            (text.as_bytes(), &detect_only, CompressionType::None),
+            (
+                brotli.as_slice(),
+                &Flags::default(),
+                CompressionType::Brotli,
+            ),
+            (brotli.as_slice(), &detect_only, CompressionType::None),
+            (brotli.as_slice(), &hinted, CompressionType::Brotli),
+        ] {
+            let mut stream = input;
+            let (kind, stream) = detect_with_peek(&mut stream, flags, 0)?;
+            assert_eq!(expected, kind, "{}", flags.detect_only);
+            if flags.detect_only {
+                assert!(stream.len() <= registry::longest_magic().max(DETECT_BUFFER_SIZE));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uncompressed_tar_is_recognised_and_passed_through() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut archive);
+            let mut header = tar::Header::new_ustar();
+            header.set_size(14);
+            header.set_cksum();
+            builder.append_data(&mut header, "a.txt", "this is a test".as_bytes())?;
+            builder.finish()?;
+        }
+
+        let flags = Flags::default();
+        let (kind, mut stream, confidence) = detect_with_confidence(archive.as_slice(), &flags)?;
+        assert_eq!(
+            (CompressionType::None, Confidence::NoMatch),
+            (kind, confidence)
+        );
+        assert!(looks_like_tar(stream.peeked()));
+        let mut output_stream = Vec::new();
+        crate::Context::new_from_stream(&mut stream, &mut output_stream, kind, &flags)?
+            .translate_stream()?;
+        assert_eq!(archive, output_stream);
+
+        assert!(!looks_like_tar(&archive[..TAR_BLOCK_SIZE - 1]));
+        assert!(!looks_like_tar("this is a test\n".repeat(100).as_bytes()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_dictionary_is_recognised() -> Result<()> {
+        let samples: Vec<String> = (0..1000)
+            .map(|i| format!("{{\"id\": {i}, \"name\": \"test {}\"}}", i * 7))
+            .collect();
+        let dictionary = zstd::dict::from_samples(&samples, 4096)?;
+
+        let flags = Flags::default();
+        let (kind, stream, confidence) = detect_with_confidence(dictionary.as_slice(), &flags)?;
+        assert_eq!(
+            (CompressionType::None, Confidence::NoMatch),
+            (kind, confidence)
+        );
+        assert!(looks_like_zstd_dictionary(stream.peeked()));
+
+        let compressed_stream = zstd::encode_all(samples.concat().as_bytes(), 3)?;
+        assert!(!looks_like_zstd_dictionary(&compressed_stream));
+        assert!(!looks_like_zstd_dictionary(&ZSTD_DICTIONARY_MAGIC[..3]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_encoding_hints() -> Result<()> {
+        for (hint, expected) in [
+            ("br", Some(CompressionType::Brotli)),
+            ("BR", Some(CompressionType::Brotli)),
+            ("brotli", Some(CompressionType::Brotli)),
+            ("gzip", Some(CompressionType::Gzip)),
+            ("x-gzip", Some(CompressionType::Gzip)),
+            ("Deflate", Some(CompressionType::Deflate)),
+            ("zstd", Some(CompressionType::Zstd)),
+            ("compress", Some(CompressionType::LzwCompress)),
+            ("identity", Some(CompressionType::None)),
+            ("Identity", Some(CompressionType::None)),
+            ("unknown", None),
+            ("rot13", None),
+        ] {
+            assert_eq!(expected, CompressionType::from_hint(hint), "{hint}");
+        }
+
+        // tokens for magic-less formats pick them, the rest leave it to the
+        // magic
+        let no_magic: &[u8] = &[0x0b, 0x06, 0x80, 0x74, 0x68, 0x69, 0x73, 0x03];
+        let gzip: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        for (input, hint, expected) in [
+            (no_magic, "br", CompressionType::Brotli),
+            (no_magic, "Br", CompressionType::Brotli),
+            (no_magic, "identity", CompressionType::None),
+            (no_magic, "rot13", CompressionType::None),
+            (gzip, "gzip", CompressionType::Gzip),
+            (gzip, "zstd", CompressionType::Gzip),
+            (gzip, "identity", CompressionType::Gzip),
+            (gzip, "rot13", CompressionType::Gzip),
+        ] {
+            let flags = Flags {
+                hint: hint.into(),
+                ..Default::default()
+            };
+            assert_eq!(expected, detect_compression_type(input, &flags), "{hint}");
+        }
+
+        Ok(())
+    }
+}
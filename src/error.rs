@@ -0,0 +1,212 @@
+use std::io;
+
+use crate::CompressionType;
+
+/// Why decompressing (and possibly recompressing) a stream failed.
+#[derive(Debug, thiserror::Error)]
+pub enum DecompressError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(
+        "couldn't detect a compression type for the input, brotli and raw deflate need a hint"
+    )]
+    UnknownFormat,
+
+    #[error("the input is a zstd dictionary, not a compressed stream, pass it as --dict to decode what was written with it, or --force to write it out as is")]
+    ZstdDictionary,
+
+    #[error("{0}")]
+    UnsupportedOutput(String),
+
+    #[error("{0}")]
+    UnsupportedInput(String),
+
+    /// `offset` counts the bytes decompressed before the codec gave up.
+    #[error("corrupt {format} stream after {offset} decompressed bytes")]
+    Corrupt {
+        format: CompressionType,
+        offset: u64,
+        source: io::Error,
+    },
+
+    #[error("recompressing the {format} stream doesn't reproduce the input, first difference at byte {offset}")]
+    NotReproduced {
+        format: CompressionType,
+        offset: u64,
+    },
+
+    #[error("the decompressed {format} stream is not a tar archive")]
+    NotTar { format: CompressionType },
+
+    /// An archive whose layout is broken, found before anything in it was
+    /// decompressed.
+    #[error("corrupt {format} archive")]
+    CorruptArchive {
+        format: CompressionType,
+        source: io::Error,
+    },
+
+    #[error("unexpected data after the end of the {format} stream")]
+    TrailingData { format: CompressionType },
+
+    #[error("the zip archive has several files, pick one with --entry: {entries}")]
+    ZipEntryNeeded { entries: String },
+
+    #[error("the zip archive has no file named `{name}`")]
+    ZipEntryNotFound { name: String },
+
+    #[error("the 7z archive has several files, pick one with --entry: {entries}")]
+    SevenZEntryNeeded { entries: String },
+
+    #[error("the 7z archive has no file named `{name}`")]
+    SevenZEntryNotFound { name: String },
+
+    #[error("the ar archive has several members, pick one with --entry: {members}")]
+    ArMemberNeeded { members: String },
+
+    #[error("the ar archive has no member named `{name}`")]
+    ArMemberNotFound { name: String },
+
+    /// The stop flag was raised, e.g. by Ctrl-C. The output was still
+    /// finished, so it's valid but cut short.
+    #[error("interrupted, the output stops short")]
+    Interrupted,
+
+    #[error("invalid --wrap input: {0}")]
+    InvalidWrapper(String),
+
+    #[error("the brotli stream's window is 2^{found} bytes, not the 2^{expected} --brotli-window asks for")]
+    BrotliWindowMismatch { expected: u32, found: u32 },
+
+    #[error("the data is still compressed after {depth} layers, pass a larger --max-depth to keep going")]
+    TooDeep { depth: u32 },
+
+    #[error("raw lzma2 input needs --dict-size, the stream doesn't record it")]
+    MissingDictSize,
+
+    #[error(
+        "the zstd frame needs a window larger than 2^{window_log} bytes, pass the --long it was written with, up to 31"
+    )]
+    ZstdWindowTooLarge { window_log: u32 },
+
+    #[error("this zstd frame uses {feature}")]
+    ZstdUnsupported { feature: &'static str },
+
+    #[error("the decompressed data is larger than --max-output {limit} bytes, possible decompression bomb")]
+    OutputLimitExceeded { limit: u64 },
+
+    #[error("decoding the stream needs more memory than --memory-limit {limit} bytes allows")]
+    MemoryLimitExceeded { limit: u64 },
+}
+
+// carried through the io::Error a decoder returns, so `from_codec` can tell
+// it apart from corruption
+#[derive(Debug, thiserror::Error)]
+#[error("zstd window larger than 2^{0} bytes")]
+pub(crate) struct ZstdWindowTooLarge(pub u32);
+
+// likewise for --max-output running out
+#[derive(Debug, thiserror::Error)]
+#[error("decompressed data larger than {0} bytes")]
+pub(crate) struct OutputLimitExceeded(pub u64);
+
+// likewise for a decoder that would need more than --memory-limit
+#[derive(Debug, thiserror::Error)]
+#[error("needs more than {0} bytes of memory")]
+pub(crate) struct MemoryLimitExceeded(pub u64);
+
+// an error from writing the output, which says nothing about the input even
+// when it shares a kind with corrupt data
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub(crate) struct OutputError(pub io::Error);
+
+impl OutputError {
+    pub(crate) fn wrap(error: io::Error) -> io::Error {
+        io::Error::new(error.kind(), OutputError(error))
+    }
+}
+
+// likewise for reading the input, e.g. a URL whose connection drops
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub(crate) struct InputError(pub io::Error);
+
+impl InputError {
+    pub(crate) fn wrap(error: io::Error) -> io::Error {
+        io::Error::new(error.kind(), InputError(error))
+    }
+}
+
+// likewise for a frame that needs something this decoder can't do
+#[derive(Debug, thiserror::Error)]
+#[error("zstd frame uses {0}")]
+pub(crate) struct ZstdUnsupported(pub &'static str);
+
+impl DecompressError {
+    // codecs report bad data as any of these, while the rest come from the
+    // underlying reader or writer
+    pub(crate) fn from_codec(format: CompressionType, offset: u64, error: io::Error) -> Self {
+        if error.get_ref().is_some_and(|e| e.is::<OutputError>()) {
+            let output = error.into_inner().map(|e| e.downcast::<OutputError>());
+            let Some(Ok(output)) = output else {
+                unreachable!("checked above")
+            };
+            return DecompressError::Io(output.0);
+        }
+        if error.get_ref().is_some_and(|e| e.is::<InputError>()) {
+            let input = error.into_inner().map(|e| e.downcast::<InputError>());
+            let Some(Ok(input)) = input else {
+                unreachable!("checked above")
+            };
+            return DecompressError::Io(input.0);
+        }
+        if let Some(ZstdWindowTooLarge(window_log)) = error.get_ref().and_then(|e| e.downcast_ref())
+        {
+            return DecompressError::ZstdWindowTooLarge {
+                window_log: *window_log,
+            };
+        }
+        if let Some(OutputLimitExceeded(limit)) = error.get_ref().and_then(|e| e.downcast_ref()) {
+            return DecompressError::OutputLimitExceeded { limit: *limit };
+        }
+        if let Some(MemoryLimitExceeded(limit)) = error.get_ref().and_then(|e| e.downcast_ref()) {
+            return DecompressError::MemoryLimitExceeded { limit: *limit };
+        }
+        if let Some(ZstdUnsupported(feature)) = error.get_ref().and_then(|e| e.downcast_ref()) {
+            return DecompressError::ZstdUnsupported { feature };
+        }
+
+        match error.kind() {
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::UnexpectedEof => DecompressError::Corrupt {
+                format,
+                offset,
+                source: error,
+            },
+            // `Other` is anything at all, but these decoders report bad data
+            // as it, and errors reading the input were told apart above
+            io::ErrorKind::Other
+                if matches!(
+                    format,
+                    CompressionType::Lz4 | CompressionType::Snappy | CompressionType::Zstd
+                ) =>
+            {
+                DecompressError::Corrupt {
+                    format,
+                    offset,
+                    source: error,
+                }
+            }
+            _ => DecompressError::Io(error),
+        }
+    }
+}
+
+impl From<liblzma::stream::Error> for DecompressError {
+    fn from(error: liblzma::stream::Error) -> Self {
+        DecompressError::Io(error.into())
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::detect::read_prefix;
+
+const ZSTD_MAGIC: u32 = 0xfd2f_b528;
+// any of 0x184d2a50 to 0x184d2a5f
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xffff_fff0;
+
+const BLOCK_HEADER_SIZE: usize = 3;
+const CHECKSUM_SIZE: u64 = 4;
+
+/// What a zstd frame's header says about it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ZstdFrame {
+    Data {
+        window_size: u64,
+        /// Only known if the encoder was told it up front.
+        content_size: Option<u64>,
+        dictionary_id: Option<u32>,
+        checksum: bool,
+        /// The whole frame, from its magic to its checksum.
+        compressed_size: u64,
+    },
+    /// Data libzstd steps over, like the seekable format's seek table. `size`
+    /// doesn't count the 8-byte header.
+    Skippable { magic: u32, size: u32 },
+}
+
+impl fmt::Display for ZstdFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZstdFrame::Data {
+                window_size,
+                content_size,
+                dictionary_id,
+                checksum,
+                compressed_size,
+            } => {
+                write!(f, "{compressed_size} bytes, window {window_size} bytes, ")?;
+                match content_size {
+                    Some(size) => write!(f, "content size {size} bytes, ")?,
+                    None => write!(f, "content size unknown, ")?,
+                }
+                match dictionary_id {
+                    Some(id) => write!(f, "dictionary {id}, ")?,
+                    None => write!(f, "no dictionary, ")?,
+                }
+                match checksum {
+                    true => write!(f, "checksum"),
+                    false => write!(f, "no checksum"),
+                }
+            }
+            ZstdFrame::Skippable { magic, size } => {
+                write!(f, "skippable, magic {magic:#010x}, {size} bytes")
+            }
+        }
+    }
+}
+
+/// Walks every frame of a zstd stream and reads its header. Blocks are
+/// stepped over by the sizes in their own headers, so nothing is
+/// decompressed and nothing is checked beyond the framing. Frames from the
+/// pre-1.0 formats aren't understood.
+pub fn read_zstd_frames<R: Read>(mut input: R) -> io::Result<Vec<ZstdFrame>> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut magic = [0; 4];
+        match read_prefix(&mut input, &mut magic)? {
+            0 => break,
+            4 => {}
+            _ => return Err(truncated()),
+        }
+        let start = offset;
+        offset += 4;
+        let magic = u32::from_le_bytes(magic);
+
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let size = u32::from_le_bytes(read_array(&mut input, &mut offset)?);
+            skip(&mut input, size.into(), &mut offset)?;
+            frames.push(ZstdFrame::Skippable { magic, size });
+            continue;
+        }
+        if magic != ZSTD_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no zstd frame at byte {start}, found magic {magic:#010x}"),
+            ));
+        }
+
+        let [descriptor] = read_array(&mut input, &mut offset)?;
+        if descriptor & 0x08 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the zstd frame at byte {start} sets a reserved header bit"),
+            ));
+        }
+        let single_segment = descriptor & 0x20 != 0;
+        let checksum = descriptor & 0x04 != 0;
+
+        let window_size = if single_segment {
+            None
+        } else {
+            let [window] = read_array(&mut input, &mut offset)?;
+            let base = 1u64 << (10 + (window >> 3));
+            Some(base + base / 8 * u64::from(window & 0x07))
+        };
+        let dictionary_id = match descriptor & 0x03 {
+            0 => None,
+            1 => Some(u32::from(read_array::<_, 1>(&mut input, &mut offset)?[0])),
+            2 => Some(u32::from(u16::from_le_bytes(read_array(
+                &mut input,
+                &mut offset,
+            )?))),
+            _ => Some(u32::from_le_bytes(read_array(&mut input, &mut offset)?)),
+        }
+        // 0 means none, just spelled out
+        .filter(|&id| id != 0);
+        let content_size = match (descriptor >> 6, single_segment) {
+            (0, false) => None,
+            (0, true) => Some(u64::from(read_array::<_, 1>(&mut input, &mut offset)?[0])),
+            (1, _) => {
+                Some(u64::from(u16::from_le_bytes(read_array(&mut input, &mut offset)?)) + 256)
+            }
+            (2, _) => Some(u64::from(u32::from_le_bytes(read_array(
+                &mut input,
+                &mut offset,
+            )?))),
+            _ => Some(u64::from_le_bytes(read_array(&mut input, &mut offset)?)),
+        };
+
+        loop {
+            let header: [u8; BLOCK_HEADER_SIZE] = read_array(&mut input, &mut offset)?;
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+            let size = u64::from(header >> 3);
+            match (header >> 1) & 0x03 {
+                // raw and compressed blocks carry `size` bytes, RLE ones a
+                // single byte repeated `size` times
+                0 | 2 => skip(&mut input, size, &mut offset)?,
+                1 => skip(&mut input, 1, &mut offset)?,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the zstd frame at byte {start} has a block of the reserved type"),
+                    ))
+                }
+            }
+            if header & 0x01 != 0 {
+                break;
+            }
+        }
+        if checksum {
+            skip(&mut input, CHECKSUM_SIZE, &mut offset)?;
+        }
+
+        frames.push(ZstdFrame::Data {
+            // a single segment frame's window is all of it
+            window_size: window_size.or(content_size).unwrap_or(0),
+            content_size,
+            dictionary_id,
+            checksum,
+            compressed_size: offset - start,
+        });
+    }
+
+    Ok(frames)
+}
+
+fn read_array<R: Read, const N: usize>(input: &mut R, offset: &mut u64) -> io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    if read_prefix(input, &mut buffer)? < N {
+        return Err(truncated());
+    }
+    *offset += N as u64;
+    Ok(buffer)
+}
+
+fn skip<R: Read>(input: &mut R, len: u64, offset: &mut u64) -> io::Result<()> {
+    if io::copy(&mut input.take(len), &mut io::sink())? < len {
+        return Err(truncated());
+    }
+    *offset += len;
+    Ok(())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the zstd stream ends partway through a frame",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use color_eyre::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_frame_headers_are_read() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut stream = Vec::new();
+
+        // the size is known up front, so it's recorded
+        let known = zstd::bulk::compress(expected.as_bytes(), 3)?;
+        stream.extend_from_slice(&known);
+
+        let mut encoder = zstd::Encoder::new(&mut stream, 19)?;
+        encoder.include_checksum(true)?;
+        encoder.write_all(expected.as_bytes())?;
+        encoder.finish()?;
+        let streamed = stream.len() - known.len();
+
+        stream.extend_from_slice(&0x184d_2a5eu32.to_le_bytes());
+        stream.extend_from_slice(&3u32.to_le_bytes());
+        stream.extend_from_slice(b"abc");
+
+        // single segment, a 1-byte dictionary ID and content size, and a
+        // raw block, by hand
+        stream.extend_from_slice(&ZSTD_MAGIC.to_le_bytes());
+        stream.extend_from_slice(&[0x25, 7, 5, 5 << 3 | 1, 0, 0]);
+        stream.extend_from_slice(b"hello");
+        stream.extend_from_slice(&[0; 4]);
+
+        let frames = read_zstd_frames(stream.as_slice())?;
+        assert_eq!(4, frames.len());
+        assert!(matches!(
+            frames[0],
+            ZstdFrame::Data {
+                content_size: Some(15000),
+                dictionary_id: None,
+                checksum: false,
+                compressed_size,
+                ..
+            } if compressed_size == known.len() as u64
+        ));
+        assert!(matches!(
+            frames[1],
+            ZstdFrame::Data {
+                content_size: None,
+                checksum: true,
+                compressed_size,
+                window_size,
+                ..
+            } if compressed_size == streamed as u64 && window_size >= 1 << 20
+        ));
+        assert_eq!(
+            ZstdFrame::Skippable {
+                magic: 0x184d_2a5e,
+                size: 3
+            },
+            frames[2]
+        );
+        assert_eq!(
+            ZstdFrame::Data {
+                window_size: 5,
+                content_size: Some(5),
+                dictionary_id: Some(7),
+                checksum: true,
+                compressed_size: 19,
+            },
+            frames[3]
+        );
+
+        assert!(read_zstd_frames(&stream[..stream.len() - 1]).is_err());
+        assert!(read_zstd_frames(b"this is a test".as_slice()).is_err());
+
+        Ok(())
+    }
+}
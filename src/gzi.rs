@@ -0,0 +1,171 @@
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A `.gzi` index, as written by `bgzip --index` and pigz: a little-endian
+/// u64 count of entries, then that many pairs of u64s, each the compressed
+/// offset of a point where decoding can start and the uncompressed offset it
+/// starts at. The start of the file is implied and not listed.
+///
+/// A point is either the start of a gzip member, as in bgzip's BGZF blocks,
+/// or a full flush in the middle of one, as `pigz --independent` writes,
+/// where the deflate stream carries on with no history from before it.
+pub struct GzipIndex {
+    // (compressed, uncompressed), sorted, starting with (0, 0)
+    points: Vec<(u64, u64)>,
+}
+
+impl GzipIndex {
+    pub fn read<R: Read>(mut index: R) -> io::Result<Self> {
+        let count = read_u64(&mut index)?;
+        let mut points = vec![(0, 0)];
+        for _ in 0..count {
+            let point = (read_u64(&mut index)?, read_u64(&mut index)?);
+            let &(compressed, uncompressed) = points.last().unwrap();
+            if point.0 < compressed || point.1 < uncompressed {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the index's offsets aren't in order",
+                ));
+            }
+            points.push(point);
+        }
+
+        Ok(Self { points })
+    }
+}
+
+/// Decompresses `range` of the decompressed data of a gzip file with a `.gzi`
+/// index, starting from the last index point at or before it rather than the
+/// start of the file. A range reaching past the end is cut short.
+pub fn decompress_gzip_range<R: Read + Seek, W: Write>(
+    file: &mut R,
+    index: &GzipIndex,
+    range: Range<u64>,
+    output: &mut W,
+) -> io::Result<u64> {
+    if range.start >= range.end {
+        return Ok(0);
+    }
+    let &(compressed, uncompressed) = index
+        .points
+        .iter()
+        .rev()
+        .find(|(_, uncompressed)| *uncompressed <= range.start)
+        .unwrap();
+
+    file.seek(SeekFrom::Start(compressed))?;
+    let mut input = BufReader::new(file);
+    // a flush point inside a member is plain deflate, with the member's
+    // header long gone
+    let mut decoder: Box<dyn Read + '_> = if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Box::new(flate2::bufread::MultiGzDecoder::new(input))
+    } else {
+        Box::new(flate2::bufread::DeflateDecoder::new(input))
+    };
+    io::copy(
+        &mut (&mut decoder).take(range.start - uncompressed),
+        &mut io::sink(),
+    )?;
+    io::copy(&mut decoder.take(range.end - range.start), output)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::Result;
+    use flate2::{Compress, Compression, FlushCompress};
+
+    use super::*;
+
+    const CHUNKS: [&[u8]; 3] = [b"0123456789", b"abcdefghij", b"ABCDEFGHIJ"];
+
+    fn index(points: &[(u64, u64)]) -> Vec<u8> {
+        let mut index = (points.len() as u64).to_le_bytes().to_vec();
+        for (compressed, uncompressed) in points {
+            index.extend_from_slice(&compressed.to_le_bytes());
+            index.extend_from_slice(&uncompressed.to_le_bytes());
+        }
+        index
+    }
+
+    fn assert_ranges(stream: &[u8], index: &GzipIndex) -> Result<()> {
+        let mut file = io::Cursor::new(stream);
+        for (range, expected) in [
+            (5..15, b"56789abcde".as_slice()),
+            (10..20, b"abcdefghij"),
+            (28..100, b"IJ"),
+            (7..7, b""),
+        ] {
+            let mut output = Vec::new();
+            decompress_gzip_range(&mut file, index, range, &mut output)?;
+            assert_eq!(expected, output);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_from_member_index() -> Result<()> {
+        // one member per chunk, like bgzip
+        let mut stream = Vec::new();
+        let mut points = Vec::new();
+        for (i, chunk) in CHUNKS.iter().enumerate() {
+            if i > 0 {
+                points.push((stream.len() as u64, 10 * i as u64));
+            }
+            let mut encoder = flate2::write::GzEncoder::new(&mut stream, Compression::default());
+            encoder.write_all(chunk)?;
+            encoder.finish()?;
+        }
+
+        let index = GzipIndex::read(index(&points).as_slice())?;
+        assert_ranges(&stream, &index)
+    }
+
+    #[test]
+    fn test_range_from_flush_point_index() -> Result<()> {
+        // one member with a full flush after each chunk, like `pigz -i`
+        let mut stream = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        let mut points = Vec::new();
+        let mut compress = Compress::new(Compression::default(), false);
+        for (i, chunk) in CHUNKS.iter().enumerate() {
+            if i > 0 {
+                points.push((stream.len() as u64, 10 * i as u64));
+            }
+            let flush = if i == CHUNKS.len() - 1 {
+                FlushCompress::Finish
+            } else {
+                FlushCompress::Full
+            };
+            stream.reserve(1024);
+            compress.compress_vec(chunk, &mut stream, flush)?;
+        }
+        let mut crc = crc32fast::Hasher::new();
+        CHUNKS.iter().for_each(|chunk| crc.update(chunk));
+        stream.extend_from_slice(&crc.finalize().to_le_bytes());
+        stream.extend_from_slice(&30u32.to_le_bytes());
+
+        let index = GzipIndex::read(index(&points).as_slice())?;
+        assert_ranges(&stream, &index)?;
+
+        // it's still an ordinary gzip stream too
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(stream.as_slice()).read_to_end(&mut decompressed)?;
+        assert_eq!(CHUNKS.concat(), decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unordered_index_is_rejected() {
+        let index = index(&[(100, 20), (50, 30)]);
+        assert!(GzipIndex::read(index.as_slice()).is_err());
+    }
+}
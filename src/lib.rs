@@ -0,0 +1,738 @@
+use std::io::{BufWriter, LineWriter, Read, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::Result;
+
+mod archive;
+mod bounded;
+mod checksum;
+mod count;
+mod ctx;
+mod detect;
+mod error;
+mod frames;
+mod gzi;
+mod lzw;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+mod peek;
+pub mod registry;
+mod seekable;
+mod sevenz;
+mod tee;
+mod terminal;
+mod verify;
+
+pub use checksum::ChecksumAlgorithm;
+pub use count::LineCounter;
+pub use ctx::{
+    auto_decoder, gzip_uncompressed_size, transcode_bytes, uncompressed_size_hint,
+    xz_uncompressed_size, BenchResult, CompressionLevels, Context, DecodeBenchResult, XzCheck,
+};
+pub use detect::{
+    detect_stream_characteristics, detect_with_confidence, hint_disagrees, looks_like_tar,
+    looks_like_text, looks_like_zstd_dictionary, CompressionType, Confidence,
+};
+pub use error::DecompressError;
+pub use frames::{read_zstd_frames, ZstdFrame};
+pub use gzi::{decompress_gzip_range, GzipIndex};
+pub use peek::PeekReader;
+pub use seekable::{decompress_range, SeekTable};
+pub use tee::TeeInput;
+pub use terminal::TerminalGuard;
+
+#[derive(Debug, Default, Clone, Parser)]
+pub struct Flags {
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Only print hard errors to stderr, no hints or progress"
+    )]
+    pub quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Print the detected input type, the output type, and how many bytes were read and written on stderr. Overridden by --quiet"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        index = 1,
+        default_value = "unknown",
+        help = "Hint for the compression type, e.g. `brotli`, or an HTTP Content-Encoding token like `br`. Only needed for brotli and raw deflate, which have no magic, when the file isn't named .br or .deflate. Anything else with a `.` or `/` in it is taken for the first file"
+    )]
+    pub hint: String,
+
+    #[arg(
+        index = 2,
+        help = "Read from these files or http(s) URLs instead of stdin, one after another. Files are never modified or removed"
+    )]
+    pub files: Vec<PathBuf>,
+
+    #[arg(
+        long = "force-input-type",
+        help = "Skip detection and decompress the input as this type, e.g. `deflate`"
+    )]
+    pub input_type: Option<CompressionType>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with = "input_type",
+        help = "Decompress the input as raw deflate, with no zlib or gzip header. It has no magic, so it can't be detected, and may even look like zlib"
+    )]
+    pub raw_deflate: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["exec", "range"],
+        help = "Discard the first N bytes of the input before detecting its type, e.g. junk some tool wrote in front of the compressed stream. A UTF-8 BOM in front of a known magic is always skipped"
+    )]
+    pub strip_leading: Option<u64>,
+
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        help = "Force the output to be compressed with the given type, e.g. `brotli`. Defaults to the type matching the --output file's extension, or none. A comma-separated list compresses again with each in turn, e.g. `zstd,xz` for zstd wrapped in xz, with the same options for each"
+    )]
+    pub output_type: Vec<CompressionType>,
+
+    #[arg(
+        long,
+        help = "Write to this file instead of stdout. If it's a directory, the output is named after the input file"
+    )]
+    pub output: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        allow_negative_numbers = true,
+        help = "Compression level for the output, on the output format's own scale. Defaults to 6 for every format, rather than each library's own default"
+    )]
+    pub level: Option<i32>,
+
+    #[arg(
+        short,
+        long,
+        help = "Worker threads for zstd output and xz input, or 0 for one per logical CPU. xz input is only decoded in parallel if it was written in blocks, e.g. by `xz -T0`"
+    )]
+    pub threads: Option<u32>,
+
+    #[arg(long, help = "zstd dictionary to decompress and/or compress with")]
+    pub dict: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(4096..=u32::MAX as u64),
+        help = "Dictionary size the stream was written with, required for raw lzma2 input since it isn't stored anywhere in the stream"
+    )]
+    pub dict_size: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "WINDOW_LOG",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(10..=31),
+        help = "Largest zstd window to accept when decompressing, as a power of two. Streams written with `zstd --long=N` for N above 27 need this [default: 27]"
+    )]
+    pub long: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Print the detected compression type (and size, if known) and exit. A gzip file's size is read from its end, so it's modulo 4 GiB, and only covers the last member"
+    )]
+    pub list: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["list", "range", "dry_run", "verify", "bench", "output", "exec", "pipe_to"],
+        help = "Print the detected type's name, as --force-input-type takes it, or `unknown`, one line per input on stdout. Reads only what detection needs, so a brotli stream longer than a magic is only found by its hint, and never fails on what it finds, for scripts"
+    )]
+    pub detect_only: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        value_name = "FORMAT",
+        help = "How --detect-only and --list print each input: `text` to read, or `json` for one object a line on stdout, with the file (null for stdin), the type as --force-input-type names it, the uncompressed size if known, and what the type was detected from, one of `magic`, `hint`, `trial`, `forced` or `none`"
+    )]
+    pub format: ReportFormat,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["list", "detect_only", "range", "dry_run", "verify", "bench", "test", "output", "output_type", "exec", "pipe_to", "tar_list"],
+        help = "Print each zstd frame's window size, content size, dictionary ID and whether it has a checksum on stderr, read from the frame headers without decompressing anything. Fails on input that isn't zstd"
+    )]
+    pub zstd_info: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "If the decompressed stream is a tar archive, print its member names instead"
+    )]
+    pub tar_list: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["list", "detect_only", "zstd_info", "tar_list", "range", "dry_run", "verify", "test", "count", "bench", "benchmark_decode", "output_type", "exec", "pipe_to", "wrap", "unwrap"],
+        help = "Print the names of the files in a zip, 7z or ar archive, one a line, instead of extracting one. These are the names --entry takes"
+    )]
+    pub list_entries: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Decompress and recompress even when the output type is the input's, which otherwise copies the input as is, without checking it's valid. Implied by --level, --checksum, --max-output, --ignore-trailing-garbage and the gzip header options"
+    )]
+    pub recompress: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Instead of decompressing, check that recompressing with the same format and --level reproduces the input exactly"
+    )]
+    pub verify: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Print the detected input type and the output type it would be converted to on stderr, without writing any output"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["output_type", "output", "pipe_to", "exec", "list", "detect_only", "tar_list", "verify", "dry_run", "range", "bench", "wrap"],
+        help = "Decompress each input fully without writing anything, like `gzip -t`, and report it as OK or FAILED on stderr. Exits nonzero if any failed"
+    )]
+    pub test: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["output_type", "output", "pipe_to", "exec", "list", "detect_only", "tar_list", "verify", "dry_run", "range", "bench", "wrap", "test"],
+        help = "Decompress each input and print its line and byte counts on stdout instead of the content, like `wc -lc`, with a total when there's more than one"
+    )]
+    pub count: bool,
+
+    #[arg(
+        long,
+        value_name = "START..END",
+        value_parser = parse_range,
+        help = "Only extract this byte range of the decompressed data from a seekable zstd file, or a gzip file with an --index, without decompressing the rest"
+    )]
+    pub range: Option<Range<u64>>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "range",
+        help = "The .gzi index of a gzip file for --range, as written by `bgzip --index` or alongside `pigz --independent`"
+    )]
+    pub index: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Show decompression progress on stderr. Ignored with --quiet or when stderr isn't a terminal"
+    )]
+    pub progress: bool,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Flush the output after roughly every BYTES of decompressed data, so downstream readers see it sooner"
+    )]
+    pub flush_every: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        value_name = "MODE",
+        help = "How stdout is buffered: `line` writes each line as it's finished, for watching the output or piping it into `grep`, `block` writes in large chunks for throughput, and `none` writes everything straight away"
+    )]
+    pub output_buffer: OutputBuffer,
+
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Write binary output to stdout even when it's a terminal, and pass a zstd dictionary through as is. Without it, output that doesn't look like text is refused on a terminal, since it would garble the display, and a dictionary is an error"
+    )]
+    pub force: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Exit with a code that says what went wrong, for scripts: 1 for any other error, 2 for input of no known type with --decompress-only, 3 for a corrupt stream or archive, 4 for an I/O error and 5 for a timeout. Without it, every error is 1. --test exits with the first failure's code"
+    )]
+    pub numeric_exit: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Stop at the end of the first compressed stream (gzip member, zstd frame, ...) and discard anything after it. By default, data after the stream is an error. Snappy and .Z streams have no end to stop at, so they can't be read with it"
+    )]
+    pub ignore_trailing_garbage: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["list", "verify", "range", "bench"],
+        help = "Discard the first N bytes of the decompressed data, like `| tail -c +N+1`"
+    )]
+    pub skip_bytes: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "M",
+        conflicts_with_all = ["list", "verify", "range", "bench"],
+        help = "Stop after writing M bytes of decompressed data (after --skip-bytes), like `| head -c M`"
+    )]
+    pub max_bytes: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Buffer size for brotli input and output [default: 65536]"
+    )]
+    pub buffer_size: Option<usize>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        help = "Memory-map input files instead of reading them, which can be faster for large ones. The file mustn't shrink while it's read. Pipes, stdin and URLs are still streamed"
+    )]
+    pub mmap: bool,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        conflicts_with_all = ["input_type", "raw_deflate", "list", "verify", "range"],
+        help = "Decompress with this external program instead, e.g. `lzip -d`, for formats c doesn't support. It's split into arguments the way a shell would, but not run by one. The input is piped to its stdin and its stdout is the decompressed data"
+    )]
+    pub exec: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        conflicts_with_all = ["output", "list", "verify", "dry_run", "range", "bench"],
+        help = "Write the output to this command's stdin instead of stdout, e.g. `tar -x`, split into arguments like --exec, then wait for it and exit with its status"
+    )]
+    pub pipe_to: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["output", "output_type", "level", "list", "tar_list", "verify", "range", "exec"],
+        help = "Recompress the decompressed input with every supported format at its default level, and print the sizes and times on stderr instead of any output"
+    )]
+    pub bench: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["output", "output_type", "level", "list", "tar_list", "verify", "range", "exec", "bench", "test", "count"],
+        help = "Decompress each input to nowhere, timing it, and print how fast the compressed input was read and the decompressed output produced, in MB/s, on stderr. For comparing decode speed with e.g. `zstd -t`"
+    )]
+    pub benchmark_decode: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Store this original file name in the gzip output's header, for `gunzip -N` to restore"
+    )]
+    pub gzip_name: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Store this modification time, in seconds since the Unix epoch, in the gzip output's header"
+    )]
+    pub gzip_mtime: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(0..=11),
+        help = "Quality for brotli output, from 0 (fastest) to 11 (smallest) [default: 9]"
+    )]
+    pub brotli_quality: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "LGWIN",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(10..=24),
+        help = "Window size for brotli output, as a power of two [default: 22]. For brotli input, when the output is something else, the window the stream must declare instead: anything else is an error, and so isn't detected as brotli by the trial decode"
+    )]
+    pub brotli_window: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "CHECK",
+        help = "Integrity check for xz output [default: crc64]"
+    )]
+    pub xz_check: Option<XzCheck>,
+
+    #[arg(
+        long,
+        value_name = "WINDOW_LOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "27",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(10..=31),
+        help = "Enable long-distance matching for zstd output, with a window of 2^N bytes, like `zstd --long=N` [default N: 27]. Better on large, repetitive input. Decompressing a window above 27 takes --long with at least N, here or with zstd"
+    )]
+    pub zstd_long: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "The file to extract from a zip or 7z archive, or the member of an ar archive like a .deb, e.g. `data.tar.xz`. Only optional if it holds just one. A zip or 7z archive is read into memory whole, an ar member on its own"
+    )]
+    pub entry: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["output_type", "verify", "bench", "unwrap"],
+        help = "Refuse input that isn't compressed instead of passing it through, and only ever write uncompressed output. For untrusted input, together with --max-output"
+    )]
+    pub decompress_only: bool,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        conflicts_with_all = ["list", "verify", "range"],
+        help = "Abort once the decompressed data grows past BYTES, after writing that much, as a guard against decompression bombs"
+    )]
+    pub max_output: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["list", "verify", "range", "bench", "exec", "unwrap"],
+        help = "Keep decompressing while the output is itself compressed, e.g. a file gzipped twice, up to --max-depth layers. Only a matching magic counts, and archives like zip are left as they are. --max-output still caps the final output"
+    )]
+    pub recursive: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "recursive",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..),
+        help = "How many layers --recursive decompresses in all before giving up with an error [default: 8]"
+    )]
+    pub max_depth: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1024..),
+        help = "Refuse xz, lzma, lzip and zstd streams that need more than roughly BYTES of memory to decode, rather than allocating it. For zstd that's the window, capped at the largest power of two that fits. Unlimited by default"
+    )]
+    pub memory_limit: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["list", "verify", "range", "bench", "tar_list", "skip_bytes", "max_bytes"],
+        help = "Put a small header in front of the output recording the input's compression type, and the uncompressed length after it, for --unwrap to check"
+    )]
+    pub wrap: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["wrap", "input_type", "raw_deflate", "exec", "list", "verify", "range", "bench", "tar_list", "skip_bytes", "max_bytes", "ignore_trailing_garbage"],
+        help = "Read --wrap output: strip the header, decompress the payload and check its length"
+    )]
+    pub unwrap: bool,
+
+    #[arg(
+        long,
+        value_name = "ALGO",
+        help = "Hash the decompressed data as it passes through and print the digest on stderr at the end"
+    )]
+    pub checksum: Option<ChecksumAlgorithm>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["range", "list", "detect_only", "dry_run"],
+        help = "Also save the input as read, still compressed, to this file. Several inputs are saved one after another"
+    )]
+    pub save_input: Option<PathBuf>,
+}
+
+impl Flags {
+    /// The input type detection is skipped for, from `--force-input-type` or
+    /// `--raw-deflate`.
+    pub fn forced_input_type(&self) -> Option<CompressionType> {
+        self.input_type
+            .or(self.raw_deflate.then_some(CompressionType::Deflate))
+    }
+
+    /// The window brotli input has to declare, from `--brotli-window` unless
+    /// that's meant for brotli output.
+    pub fn brotli_input_window(&self) -> Option<u32> {
+        self.brotli_window
+            .filter(|_| !self.output_type.contains(&CompressionType::Brotli))
+    }
+}
+
+/// How `--detect-only` and `--list` print what they found, picked with
+/// `--format`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// How stdout is buffered, picked with `--output-buffer`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum OutputBuffer {
+    Line,
+    #[default]
+    Block,
+    None,
+}
+
+// several pipe buffers' worth, so a fast decoder isn't held up by syscalls
+const OUTPUT_BLOCK_SIZE: usize = 256 * 1024;
+
+impl OutputBuffer {
+    pub fn wrap<'a, W: Write + 'a>(self, output: W) -> Box<dyn Write + 'a> {
+        match self {
+            OutputBuffer::Line => Box::new(LineWriter::new(output)),
+            OutputBuffer::Block => Box::new(BufWriter::with_capacity(OUTPUT_BLOCK_SIZE, output)),
+            OutputBuffer::None => Box::new(FlushEveryWrite(output)),
+        }
+    }
+}
+
+// stdout has a line buffer of its own, so this is the only way past it
+struct FlushEveryWrite<W: Write>(W);
+
+impl<W: Write> Write for FlushEveryWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn parse_range(range: &str) -> Result<Range<u64>, String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected START..END, got `{range}`"))?;
+    let start = start
+        .parse()
+        .map_err(|e| format!("bad start `{start}`: {e}"))?;
+    let end = end.parse().map_err(|e| format!("bad end `{end}`: {e}"))?;
+    if start > end {
+        return Err(format!("range starts after it ends: `{range}`"));
+    }
+
+    Ok(start..end)
+}
+
+/// Detect the compression type of `input` and write its decompressed contents
+/// to `output`. Unlike the CLI, input that isn't recognized as compressed is
+/// an error rather than copied through. An empty input is left empty.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<(), DecompressError> {
+    let flags = Flags::default();
+    let (kind, mut input) = detect_stream_characteristics(input, &flags)?;
+    if kind == CompressionType::None && !input.peeked().is_empty() {
+        return Err(DecompressError::UnknownFormat);
+    }
+
+    let mut context = Context::new_from_stream(&mut input, &mut output, kind, &flags)?;
+    context.translate_stream()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Write};
+
+    use super::*;
+    use color_eyre::Result;
+
+    #[test]
+    fn test_decompress_detects_and_decompresses() -> Result<()> {
+        let expected = "this is a test";
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut compressed_stream, 6);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        decompress(compressed_stream.as_slice(), &mut output_stream)?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    // shared, so what reached it can be checked while it's still wrapped
+    #[derive(Clone, Default)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_buffer_modes() -> Result<()> {
+        let shared = Shared::default();
+        let mut output = OutputBuffer::Line.wrap(shared.clone());
+        output.write_all(b"this is")?;
+        assert_eq!(b"", shared.0.borrow().as_slice());
+        output.write_all(b" a test\nand")?;
+        assert_eq!(b"this is a test\n", shared.0.borrow().as_slice());
+
+        let shared = Shared::default();
+        let mut output = OutputBuffer::Block.wrap(shared.clone());
+        output.write_all(b"this is a test\n")?;
+        assert_eq!(b"", shared.0.borrow().as_slice());
+        output.flush()?;
+        assert_eq!(b"this is a test\n", shared.0.borrow().as_slice());
+
+        let shared = Shared::default();
+        let mut output = OutputBuffer::None.wrap(shared.clone());
+        output.write_all(b"this is")?;
+        assert_eq!(b"this is", shared.0.borrow().as_slice());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_rejects_unknown_input() {
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = decompress(b"this is a test".as_slice(), &mut output_stream);
+
+        assert!(matches!(result, Err(DecompressError::UnknownFormat)));
+        assert!(output_stream.is_empty());
+    }
+
+    #[test]
+    fn test_auto_decoder_reads_decompressed_data() -> Result<()> {
+        let expected = "this is a test";
+        let mut xz_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = liblzma::write::XzEncoder::new(&mut xz_stream, 6);
+            encoder.write_all(expected.as_bytes())?;
+        }
+        let mut lzma_stream: Vec<u8> = Vec::new();
+        {
+            let options = liblzma::stream::LzmaOptions::new_preset(6)?;
+            let stream = liblzma::stream::Stream::new_lzma_encoder(&options)?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut lzma_stream, stream);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        // a peek too short for any magic still detects both
+        for compressed_stream in [xz_stream, lzma_stream] {
+            for peek_len in [1, 64, 1024] {
+                let mut decoder =
+                    auto_decoder(io::Cursor::new(compressed_stream.clone()), peek_len)?;
+                let mut decompressed = String::new();
+                decoder.read_to_string(&mut decompressed)?;
+                assert_eq!(expected, decompressed, "peek {peek_len}");
+            }
+        }
+
+        Ok(())
+    }
+
+    // a plain reader, like a pipe or a socket, with no buffer of its own
+    struct Unbuffered<R: Read>(R);
+
+    impl<R: Read> Read for Unbuffered<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_auto_decoder_takes_any_reader() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut lzma_stream: Vec<u8> = Vec::new();
+        {
+            let options = liblzma::stream::LzmaOptions::new_preset(6)?;
+            let stream = liblzma::stream::Stream::new_lzma_encoder(&options)?;
+            let mut encoder = liblzma::write::XzEncoder::new_stream(&mut lzma_stream, stream);
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let mut decoder = auto_decoder(Unbuffered(io::Cursor::new(lzma_stream)), 64)?;
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed)?;
+        assert_eq!(expected, decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_decoder_rejects_unknown_input() -> Result<()> {
+        let result = auto_decoder(b"this is a test".as_slice(), 64);
+        assert!(matches!(result, Err(DecompressError::UnknownFormat)));
+
+        let mut decompressed = Vec::new();
+        auto_decoder(b"".as_slice(), 64)?.read_to_end(&mut decompressed)?;
+        assert!(decompressed.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::{self, BufReader, Read};
+
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+const BIT_MASK: u8 = 0x1f;
+const BLOCK_MODE: u8 = 0x80;
+
+const INIT_BITS: u32 = 9;
+const MIN_MAX_BITS: u32 = 9;
+const MAX_MAX_BITS: u32 = 16;
+const CLEAR: usize = 256;
+
+/// Streaming decoder for the LZW format written by Unix `compress` (`.Z`).
+pub struct LzwDecoder<R: Read> {
+    inner: BufReader<R>,
+    started: bool,
+
+    max_bits: u32,
+    block_mode: bool,
+    n_bits: u32,
+    max_code: usize,
+    free_entry: usize,
+    old_code: Option<usize>,
+    final_char: u8,
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+
+    bits: u32,
+    bit_count: u32,
+    // codes are written in groups of eight, and the group is padded out
+    // whenever the code width changes or the table is cleared
+    codes_in_group: usize,
+
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            inner: BufReader::new(reader),
+            started: false,
+            max_bits: MAX_MAX_BITS,
+            block_mode: true,
+            n_bits: INIT_BITS,
+            max_code: (1 << INIT_BITS) - 1,
+            free_entry: CLEAR + 1,
+            old_code: None,
+            final_char: 0,
+            prefix: vec![0; 1 << MAX_MAX_BITS],
+            suffix: (0..1usize << MAX_MAX_BITS).map(|i| i as u8).collect(),
+            bits: 0,
+            bit_count: 0,
+            codes_in_group: 0,
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 3];
+        self.inner.read_exact(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(invalid_data("not a .Z stream"));
+        }
+
+        self.max_bits = (header[2] & BIT_MASK) as u32;
+        if !(MIN_MAX_BITS..=MAX_MAX_BITS).contains(&self.max_bits) {
+            return Err(invalid_data(format!(
+                "unsupported .Z code width of {} bits",
+                self.max_bits
+            )));
+        }
+        self.block_mode = header[2] & BLOCK_MODE != 0;
+        self.free_entry = if self.block_mode { CLEAR + 1 } else { CLEAR };
+        self.set_code_width(INIT_BITS);
+
+        Ok(())
+    }
+
+    fn set_code_width(&mut self, n_bits: u32) {
+        self.n_bits = n_bits;
+        self.max_code = if n_bits == self.max_bits {
+            1 << self.max_bits
+        } else {
+            (1 << n_bits) - 1
+        };
+    }
+
+    fn read_code(&mut self) -> io::Result<Option<usize>> {
+        while self.bit_count < self.n_bits {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                // a partial code at the end is just padding
+                return Ok(None);
+            }
+            self.bits |= (byte[0] as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let code = self.bits & ((1 << self.n_bits) - 1);
+        self.bits >>= self.n_bits;
+        self.bit_count -= self.n_bits;
+        self.codes_in_group += 1;
+
+        Ok(Some(code as usize))
+    }
+
+    fn skip_to_group_end(&mut self) -> io::Result<()> {
+        while !self.codes_in_group.is_multiple_of(8) {
+            if self.read_code()?.is_none() {
+                break;
+            }
+        }
+        self.codes_in_group = 0;
+
+        Ok(())
+    }
+
+    /// Decodes the next code into `pending`, returning `false` at the end of
+    /// the stream.
+    fn decode_next(&mut self) -> io::Result<bool> {
+        loop {
+            if self.free_entry > self.max_code {
+                self.skip_to_group_end()?;
+                self.set_code_width(self.n_bits + 1);
+            }
+
+            let Some(code) = self.read_code()? else {
+                return Ok(false);
+            };
+
+            let Some(old_code) = self.old_code else {
+                if code >= CLEAR {
+                    return Err(invalid_data("corrupt .Z stream: bad first code"));
+                }
+                self.final_char = code as u8;
+                self.old_code = Some(code);
+                self.pending.push(self.final_char);
+                return Ok(true);
+            };
+
+            if code == CLEAR && self.block_mode {
+                // the entry added by the next code lands on the CLEAR slot and
+                // is never referenced, matching what `compress` does
+                self.free_entry = CLEAR;
+                self.skip_to_group_end()?;
+                self.set_code_width(INIT_BITS);
+                continue;
+            }
+
+            let mut current = code;
+            if current >= self.free_entry {
+                if current > self.free_entry {
+                    return Err(invalid_data("corrupt .Z stream: code out of range"));
+                }
+                // the KwKwK case, where the code is the one about to be defined
+                self.pending.push(self.final_char);
+                current = old_code;
+            }
+            while current >= CLEAR {
+                self.pending.push(self.suffix[current]);
+                current = self.prefix[current] as usize;
+            }
+            self.final_char = self.suffix[current];
+            self.pending.push(self.final_char);
+            self.pending.reverse();
+
+            if self.free_entry < 1 << self.max_bits {
+                self.prefix[self.free_entry] = old_code as u16;
+                self.suffix[self.free_entry] = self.final_char;
+                self.free_entry += 1;
+            }
+            self.old_code = Some(code);
+
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.started {
+            self.read_header()?;
+            self.started = true;
+        }
+
+        if self.position == self.pending.len() {
+            self.pending.clear();
+            self.position = 0;
+            if !self.decode_next()? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.pending.len() - self.position);
+        buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
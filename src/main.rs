@@ -1,52 +1,955 @@
-use std::io::Read;
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use atty::Stream;
-use clap::{ArgAction, Parser};
-use color_eyre::eyre::{Result, eyre};
-use ctx::{CompressionType, Context};
+use c::{
+    BenchResult, CompressionType, Confidence, Context, DecodeBenchResult, DecompressError, Flags,
+    ReportFormat,
+};
+use clap::{Parser, ValueEnum};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use signal_hook::consts::SIGINT;
 
-mod ctx;
+// a stalled download errors out instead of hanging forever
+const URL_READ_TIMEOUT: Duration = Duration::from_secs(30);
 
-#[derive(Debug, Parser)]
-pub struct Flags {
-    #[arg(short, long, default_value = "false", action = ArgAction::SetTrue)]
-    quiet: bool,
+// like a shell reports a command killed by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
 
-    #[arg(
-        index = 1,
-        default_value = "unknown",
-        help = "Hint for the compression type, e.g. `brotli`"
-    )]
-    hint: String,
-
-    #[arg(
-        short,
-        long,
-        help = "Force the output to be compressed with the given type, e.g. `brotli`",
-        default_value = "none"
-    )]
-    output_type: Option<CompressionType>,
-}
+// the codes --numeric-exit promises, besides 0 for success
+const EXIT_ERROR: i32 = 1;
+const EXIT_UNKNOWN_FORMAT: i32 = 2;
+const EXIT_CORRUPT: i32 = 3;
+const EXIT_IO: i32 = 4;
+const EXIT_TIMEOUT: i32 = 5;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let flags = Flags::parse();
-    if atty::is(Stream::Stdin) {
-        return Err(eyre!("input is a terminal, please pipe data via stdin!"));
+    let numeric_exit = flags.numeric_exit;
+    match run(flags) {
+        // reported the way returning it would, just with its own code
+        Err(report) if numeric_exit => {
+            eprintln!("Error: {report:?}");
+            std::process::exit(exit_code(&report));
+        }
+        result => result,
+    }
+}
+
+// the outermost error that says what went wrong decides
+fn exit_code(report: &color_eyre::Report) -> i32 {
+    let io_code = |error: &io::Error| match error.kind() {
+        io::ErrorKind::TimedOut => EXIT_TIMEOUT,
+        _ => EXIT_IO,
+    };
+    for cause in report.chain() {
+        if let Some(error) = cause.downcast_ref::<DecompressError>() {
+            return match error {
+                DecompressError::UnknownFormat => EXIT_UNKNOWN_FORMAT,
+                DecompressError::Corrupt { .. }
+                | DecompressError::CorruptArchive { .. }
+                | DecompressError::TrailingData { .. } => EXIT_CORRUPT,
+                DecompressError::Io(error) => io_code(error),
+                _ => EXIT_ERROR,
+            };
+        }
+        if let Some(error) = cause.downcast_ref::<io::Error>() {
+            return io_code(error);
+        }
+    }
+    EXIT_ERROR
+}
+
+fn run(mut flags: Flags) -> Result<()> {
+    // `c file.gz` lands the path in the hint slot, so move it over. one that
+    // looks like a path but isn't a hint is too, so a missing file is an error
+    // rather than an unknown hint
+    let hint = Path::new(&flags.hint);
+    let looks_like_path = flags.hint.contains(['.', '/', std::path::MAIN_SEPARATOR])
+        && CompressionType::from_hint(&flags.hint).is_none();
+    if hint.is_file() || is_url(hint) || looks_like_path {
+        let file = std::mem::replace(&mut flags.hint, "unknown".into());
+        flags.files.insert(0, file.into());
+    }
+    if flags.hint != "unknown"
+        && CompressionType::from_hint(&flags.hint).is_none()
+        && !flags.quiet
+        && !flags.detect_only
+    {
+        eprintln!(
+            "unknown hint `{}`, detecting the input type from its magic instead",
+            flags.hint
+        );
+    }
+    if let Some(range) = flags.range.clone() {
+        return extract_range(&flags, range);
+    }
+
+    // stdin is the one input when no files are given
+    let sources: Vec<Option<&Path>> = match flags.files.as_slice() {
+        [] => vec![None],
+        files => files.iter().map(|file| Some(file.as_path())).collect(),
+    };
+
+    // one token a line for scripts, whatever the input turns out to be
+    if flags.detect_only {
+        for &source in &sources {
+            let mut flags = flags.clone();
+            let mut input = match source {
+                // unbuffered, so nothing is read past what detection asks for
+                Some(path) if !is_url(path) => {
+                    hint_from_extension(path, &mut flags);
+                    Box::new(
+                        File::open(path)
+                            .wrap_err_with(|| format!("failed to open {}", path.display()))?,
+                    )
+                }
+                _ => open_input(source, &mut flags)?,
+            };
+            let (kind, input, confidence) = c::detect_with_confidence(&mut input, &flags)?;
+            if flags.format == ReportFormat::Json {
+                print_json(source, kind, input.peeked(), confidence, &flags)?;
+                continue;
+            }
+            match kind {
+                CompressionType::None => println!("unknown"),
+                kind => println!("{}", kind.to_possible_value().unwrap().get_name()),
+            }
+        }
+        return Ok(());
+    }
+
+    if flags.list {
+        for &source in &sources {
+            let mut flags = flags.clone();
+            let mut input = open_input(source, &mut flags)?;
+            let (kind, input, confidence) = c::detect_with_confidence(&mut input, &flags)?;
+            if flags.format == ReportFormat::Json {
+                print_json(source, kind, input.peeked(), confidence, &flags)?;
+                continue;
+            }
+            let description = match expected_size(source, kind, input.peeked()) {
+                Some(size) if kind == CompressionType::Gzip => {
+                    format!("{kind} ({size} bytes uncompressed, modulo 2^32)")
+                }
+                Some(size) => format!("{kind} ({size} bytes uncompressed)"),
+                None => format!("{kind}"),
+            };
+            match source {
+                Some(path) if sources.len() > 1 => println!("{}: {description}", path.display()),
+                _ => println!("{description}"),
+            }
+        }
+        return Ok(());
+    }
+
+    if flags.zstd_info {
+        for &source in &sources {
+            let mut flags = flags.clone();
+            let name = source.map_or("stdin".into(), |path| path.display().to_string());
+            let mut input = open_input(source, &mut flags)?;
+            let (kind, input) = c::detect_stream_characteristics(&mut input, &flags)?;
+            if kind != CompressionType::Zstd {
+                return Err(eyre!("--zstd-info needs zstd input, but {name} is {kind}"));
+            }
+            let frames = c::read_zstd_frames(input)
+                .wrap_err_with(|| format!("failed reading the zstd frames of {name}"))?;
+            if sources.len() > 1 {
+                eprintln!("{name}:");
+            }
+            for (i, frame) in frames.iter().enumerate() {
+                eprintln!("frame {i}: {frame}");
+            }
+        }
+        return Ok(());
+    }
+
+    // `--output some.zst` implies zstd output unless a type was given
+    if !flags.verify && flags.output_type.is_empty() {
+        if let Some(path) = flags.output.as_deref().filter(|path| !path.is_dir()) {
+            flags.output_type = vec![output_type_for(path, flags.quiet)];
+        }
+    }
+
+    if flags.dry_run {
+        for &source in &sources {
+            let mut flags = flags.clone();
+            let output_type = output_types(&flags);
+            if let Some(command) = &flags.exec {
+                match source {
+                    Some(path) if sources.len() > 1 => {
+                        eprintln!("{}: `{command}` -> {output_type}", path.display())
+                    }
+                    _ => eprintln!("`{command}` -> {output_type}"),
+                }
+                continue;
+            }
+            let mut input = open_input(source, &mut flags)?;
+            let (kind, _) = c::detect_stream_characteristics(&mut input, &flags)?;
+            let output_type = if flags.verify {
+                kind.to_string()
+            } else {
+                output_type
+            };
+            match source {
+                Some(path) if sources.len() > 1 => {
+                    eprintln!("{}: {kind} -> {output_type}", path.display())
+                }
+                _ => eprintln!("{kind} -> {output_type}"),
+            }
+        }
+        return Ok(());
+    }
+
+    // keeps going past a failure, so one bad file doesn't hide the next
+    if flags.test {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut failed = None;
+        for &source in &sources {
+            let name = source.map_or("stdin".into(), |path| path.display().to_string());
+            let mut sink: Box<dyn Write> = Box::new(io::sink());
+            match decompress_source(source, &flags, &mut sink, None, &stop) {
+                Ok(()) if !flags.quiet => eprintln!("{name}: OK"),
+                Ok(()) => {}
+                Err(report) => {
+                    failed = failed.or(Some(exit_code(&report)));
+                    let causes: Vec<_> = report.chain().map(|cause| cause.to_string()).collect();
+                    eprintln!("{name}: FAILED, {}", causes.join(": "));
+                }
+            }
+        }
+        if let Some(code) = failed {
+            std::process::exit(if flags.numeric_exit { code } else { EXIT_ERROR });
+        }
+        return Ok(());
+    }
+
+    // like `wc -lc` over what comes out, for sizing up compressed logs
+    if flags.count {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut total = c::LineCounter::default();
+        for &source in &sources {
+            let mut counter = c::LineCounter::default();
+            let mut sink: Box<dyn Write + '_> = Box::new(&mut counter);
+            let result = decompress_source(source, &flags, &mut sink, None, &stop);
+            drop(sink);
+            match source {
+                Some(path) => {
+                    result.wrap_err_with(|| format!("failed on {}", path.display()))?;
+                    println!("{} {} {}", counter.lines, counter.bytes, path.display());
+                }
+                None => {
+                    result?;
+                    println!("{} {}", counter.lines, counter.bytes);
+                }
+            }
+            total.lines += counter.lines;
+            total.bytes += counter.bytes;
+        }
+        if sources.len() > 1 {
+            println!("{} {} total", total.lines, total.bytes);
+        }
+        return Ok(());
+    }
+
+    let output_path = match &flags.output {
+        Some(path) => Some(output_path(path, &flags.files)?),
+        None => None,
+    };
+    let mut downstream = None;
+    let mut output: Box<dyn Write> = match (&output_path, &flags.pipe_to) {
+        (Some(path), _) => {
+            let file = File::create(path)
+                .wrap_err_with(|| format!("failed to open {} for writing", path.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        (None, Some(command)) => {
+            let mut child = spawn_downstream(command)?;
+            let stdin = child.stdin.take().expect("stdin is piped");
+            downstream = Some((command, child));
+            Box::new(BufWriter::new(stdin))
+        }
+        // Rust's stdout never translates line endings, even on Windows, so it
+        // must stay unwrapped by anything text-mode
+        (None, None) => flags.output_buffer.wrap(guarded_stdout(&flags)),
+    };
+    let save_input = match &flags.save_input {
+        Some(path) => Some(
+            File::create(path)
+                .wrap_err_with(|| format!("failed to open {} for writing", path.display()))?,
+        ),
+        None => None,
+    };
+
+    // best effort: the first Ctrl-C stops reading so the output encoder still
+    // gets to write its trailer. a read that blocks holds that up, so a second
+    // one exits right away
+    let stop = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register_conditional_shutdown(SIGINT, EXIT_INTERRUPTED, stop.clone())?;
+    signal_hook::flag::register(SIGINT, stop.clone())?;
+
+    let result = sources
+        .iter()
+        .try_for_each(|&source| {
+            let result = decompress_source(source, &flags, &mut output, save_input.as_ref(), &stop);
+            match source {
+                Some(path) => result.wrap_err_with(|| format!("failed on {}", path.display())),
+                None => result,
+            }
+        })
+        .and_then(|()| Ok(output.flush()?));
+    let result = ignore_broken_pipe(result);
+    let interrupted = result.as_ref().is_err_and(|report| {
+        report
+            .chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(DecompressError::Interrupted)))
+    });
+    if interrupted {
+        // what was written is a valid stream, just a short one, so keep it
+        let _ = output.flush();
+    }
+    // closes the downstream command's stdin, so it sees the end of its input
+    drop(output);
+    if result.is_err() && !interrupted {
+        // don't leave a truncated file behind, e.g. after a gzip CRC mismatch.
+        // anything already written to stdout can't be taken back
+        if let Some(path) = &output_path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    if let Some((command, mut child)) = downstream {
+        let status = child
+            .wait()
+            .wrap_err_with(|| format!("failed waiting for `{command}`"))?;
+        if !interrupted {
+            result?;
+            return exit_like(status);
+        }
+    }
+    if interrupted {
+        if !flags.quiet {
+            eprintln!("interrupted, the output stops short");
+        }
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+
+    result
+}
+
+// `--pipe-to 'tar -x'` instead of `| tar -x`, so its exit code is ours. it
+// shares our process group, so a Ctrl-C from the terminal reaches it too
+fn spawn_downstream(command: &str) -> Result<Child> {
+    let (program, args) = split_command(command, "--pipe-to")?;
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to run `{command}`"))
+}
+
+// exits the way a shell reports a pipeline's last command, including 128 plus
+// the signal number if it was killed
+fn exit_like(status: ExitStatus) -> Result<()> {
+    if status.success() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            std::process::exit(128 + signal);
+        }
+    }
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+// each input is detected on its own, so `c a.gz b.xz` works like `zcat`
+fn decompress_source(
+    source: Option<&Path>,
+    flags: &Flags,
+    output: &mut Box<dyn Write + '_>,
+    save_input: Option<&File>,
+    stop: &Arc<AtomicBool>,
+) -> Result<()> {
+    // a URL's Content-Encoding only applies to that one input
+    let mut flags = flags.clone();
+    let input = open_input(source, &mut flags)?;
+    // before detection, so the peeked prefix is saved too. a clone of the one
+    // file, so --exec can still move the input to its feeder thread
+    let input: Box<dyn Read + Send> = match save_input {
+        Some(file) => Box::new(c::TeeInput::new(input, file.try_clone()?)),
+        None => input,
+    };
+    if let Some(command) = flags.exec.clone() {
+        return decompress_with(&command, source, input, &flags, output);
+    }
+    let (kind, mut input, confidence) = c::detect_with_confidence(input, &flags)?;
+    let magic = input.peeked();
+    if let Confidence::Truncated { candidate, missing } = confidence {
+        if !flags.quiet {
+            let name = source.map_or("stdin".into(), |path| path.display().to_string());
+            eprintln!(
+                "{name}: input ended {missing} bytes short of a {candidate} magic, passing it through as is"
+            );
+        }
+    }
+    // the magic wins, but a hint that names something else is likely a mistake
+    if let Some(hinted) = c::hint_disagrees(kind, magic, &flags) {
+        if !flags.quiet {
+            let name = source.map_or("stdin".into(), |path| path.display().to_string());
+            let forced = hinted.to_possible_value().unwrap();
+            eprintln!(
+                "{name}: hint says {hinted} but magic indicates {kind}, using {kind}. pass --force-input-type {} to decode it as {hinted} anyway",
+                forced.get_name()
+            );
+        }
+    }
+    let how = if flags.forced_input_type().is_some() {
+        "forced"
+    } else {
+        "detected"
+    };
+    if flags.test && kind == CompressionType::None && !magic.is_empty() {
+        return Err(c::DecompressError::UnknownFormat.into());
+    }
+    if flags.decompress_only && kind == CompressionType::None && !magic.is_empty() {
+        return Err(c::DecompressError::UnknownFormat)
+            .wrap_err("--decompress-only won't pass the input through as is");
+    }
+    // a dictionary piped in where the data should have gone
+    if confidence == Confidence::NoMatch && c::looks_like_zstd_dictionary(magic) && !flags.force {
+        return Err(c::DecompressError::ZstdDictionary.into());
+    }
+    // text is what passing through is for, and compressing anything is fine,
+    // so only binary input that comes out unchanged gets a word
+    let passthrough = flags
+        .output_type
+        .iter()
+        .all(|&kind| kind == CompressionType::None);
+    if confidence == Confidence::NoMatch && passthrough && !magic.is_empty() && !flags.quiet {
+        let name = source.map_or("stdin".into(), |path| path.display().to_string());
+        // e.g. a plain tar named .tar.gz
+        if c::looks_like_tar(magic) {
+            eprintln!("{name}: this looks like an uncompressed tar archive, not a compressed stream, passing it through as is");
+        } else if c::looks_like_zstd_dictionary(magic) {
+            eprintln!("{name}: this is a zstd dictionary, not a compressed stream, passing it through as is");
+        } else if !c::looks_like_text(magic) {
+            eprintln!("{name}: no known magic, passing the input through as is");
+            eprintln!("{name}: if it's brotli or raw deflate, which have none, name it with a hint, e.g. `c brotli`");
+        }
+    }
+    // the header names the payload's type, so detection has nothing to say
+    if !flags.unwrap {
+        verbose(
+            source,
+            &flags,
+            format_args!(
+                "{how} {kind} input, read {} bytes to detect it",
+                magic.len()
+            ),
+        );
+    }
+    if magic.is_empty() {
+        // an empty input has nothing to decode, even if a type was forced
+        flags.input_type = None;
+        flags.raw_deflate = false;
+    }
+    let expected_size = expected_size(source, kind, magic);
+    if flags.verify {
+        // so --level is checked against the format being recompressed
+        flags.output_type = vec![kind];
+    }
+
+    let mut context = Context::new_from_stream(&mut input, output, kind, &flags)?
+        .with_expected_size(expected_size)
+        .with_stop_flag(stop.clone());
+    let result = if flags.tar_list {
+        context.list_tar_members().map_err(Into::into)
+    } else if flags.list_entries {
+        context.list_entries().map_err(Into::into)
+    } else if flags.verify {
+        context.verify_stream().map_err(Into::into)
+    } else if flags.bench {
+        if let Some(path) = source {
+            eprintln!("{}:", path.display());
+        }
+        context
+            .bench_stream()
+            .map(|results| print_bench(&results))
+            .map_err(Into::into)
+    } else if flags.benchmark_decode {
+        context
+            .benchmark_decode()
+            .map(|result| print_decode_bench(source, &result))
+            .map_err(Into::into)
+    } else {
+        verbose_translate(source, &flags, &mut context)
+            .map(|()| print_digest(source, context.digest(), flags.quiet))
+    };
+
+    // the decoder can stop short of the end, e.g. with
+    // --ignore-trailing-garbage, but the saved copy shouldn't
+    if result.is_ok() && save_input.is_some() {
+        io::copy(&mut input, &mut io::sink())?;
+    }
+    result
+}
+
+fn verbose_translate<R: Read, W: Write>(
+    source: Option<&Path>,
+    flags: &Flags,
+    context: &mut Context<R, W>,
+) -> Result<()> {
+    let output_type = output_types(flags);
+    verbose(source, flags, format_args!("writing {output_type} output"));
+    context.translate_stream()?;
+    if let Some((original, payload)) = context.unwrapped_types() {
+        verbose(
+            source,
+            flags,
+            format_args!("unwrapped {payload} payload, originally {original} input"),
+        );
+    }
+    verbose(
+        source,
+        flags,
+        format_args!(
+            "read {} bytes, wrote {} bytes",
+            context.input_bytes(),
+            context.output_bytes()
+        ),
+    );
+    Ok(())
+}
+
+// --verbose lines are named after the input, so several files can be told apart
+fn verbose(source: Option<&Path>, flags: &Flags, message: fmt::Arguments) {
+    if flags.verbose && !flags.quiet {
+        let name = source.map_or("stdin".into(), |path| path.display().to_string());
+        eprintln!("{name}: {message}");
+    }
+}
+
+// in the same format as `sha256sum` and friends
+fn print_digest(source: Option<&Path>, digest: Option<&str>, quiet: bool) {
+    if let (Some(digest), false) = (digest, quiet) {
+        let name = source.map_or("-".into(), |path| path.display().to_string());
+        eprintln!("{digest}  {name}");
+    }
+}
+
+// for formats without a decoder here, e.g. `--exec 'lzip -d'`. the program's
+// output is treated as plain data, so it can still be recompressed with -o
+fn decompress_with(
+    command: &str,
+    source: Option<&Path>,
+    mut input: Box<dyn Read + Send>,
+    flags: &Flags,
+    output: &mut Box<dyn Write + '_>,
+) -> Result<()> {
+    let (program, args) = split_command(command, "--exec")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .wrap_err_with(|| format!("failed to run `{command}`"))?;
+
+    // fed from another thread, or the program could block writing its output
+    // while we block writing its input
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let feeder = thread::spawn(move || io::copy(&mut input, &mut stdin));
+
+    verbose(
+        source,
+        flags,
+        format_args!("decompressing with `{command}`"),
+    );
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let result = (|| -> Result<()> {
+        let mut context =
+            Context::new_from_stream(&mut stdout, output, CompressionType::None, flags)?;
+        if flags.tar_list {
+            Ok(context.list_tar_members()?)
+        } else {
+            verbose_translate(source, flags, &mut context)?;
+            print_digest(source, context.digest(), flags.quiet);
+            Ok(())
+        }
+    })();
+    // closing its stdout stops the program if we gave up early
+    drop(stdout);
+    let status = child.wait()?;
+    let fed = feeder
+        .join()
+        .map_err(|_| eyre!("feeding the input to `{command}` panicked"))?;
+
+    result?;
+    if !status.success() && !killed_by_broken_pipe(status) {
+        return Err(eyre!("`{command}` failed: {status}"));
+    }
+    match fed {
+        // the program may stop reading once it reaches the end of its format
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            Err(e).wrap_err_with(|| format!("failed to feed the input to `{command}`"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// like a shell, don't blame the program for stopping when its output went away
+#[cfg(unix)]
+fn killed_by_broken_pipe(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(13)
+}
+
+#[cfg(not(unix))]
+fn killed_by_broken_pipe(_status: ExitStatus) -> bool {
+    false
+}
+
+fn print_bench(results: &[BenchResult]) {
+    let original = results
+        .iter()
+        .find(|result| result.kind == CompressionType::None)
+        .map_or(0, |result| result.size);
+    eprintln!(
+        "{:<12} {:>12} {:>8} {:>12}",
+        "format", "bytes", "ratio", "time"
+    );
+    for result in results {
+        let ratio = match original {
+            0 => "-".to_string(),
+            original => format!("{:.1}%", result.size as f64 * 100.0 / original as f64),
+        };
+        eprintln!(
+            "{:<12} {:>12} {:>8} {:>12}",
+            result.kind.to_string(),
+            result.size,
+            ratio,
+            format!("{:.2?}", result.elapsed)
+        );
+    }
+}
+
+// MB as in 10^6 bytes, the way `zstd -b` reports it
+fn print_decode_bench(source: Option<&Path>, result: &DecodeBenchResult) {
+    let name = source.map_or("stdin".into(), |path| path.display().to_string());
+    eprintln!(
+        "{name}: decoded {} bytes of {} into {} in {:.2?}: {:.1} MB/s in, {:.1} MB/s out",
+        result.input_size,
+        result.kind,
+        result.output_size,
+        result.elapsed,
+        result.input_rate() / 1e6,
+        result.output_rate() / 1e6
+    );
+}
+
+fn open_input(source: Option<&Path>, flags: &mut Flags) -> Result<Box<dyn Read + Send>> {
+    match source {
+        Some(url) if is_url(url) => {
+            let url = url.to_string_lossy();
+            let response = ureq::AgentBuilder::new()
+                .timeout_read(URL_READ_TIMEOUT)
+                .build()
+                .get(&url)
+                .call()
+                .wrap_err_with(|| format!("failed to fetch {url}"))?;
+            // brotli can't always be sniffed, so take the server's word for it
+            if let Some(encoding) = response.header("Content-Encoding") {
+                hint_from_content_encoding(encoding, flags);
+            }
+            Ok(Box::new(response.into_reader()))
+        }
+        Some(path) => {
+            hint_from_extension(path, flags);
+            let file =
+                File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+            // only a regular file has a fixed length to map. an empty one
+            // can't be mapped at all
+            if flags.mmap
+                && file
+                    .metadata()
+                    .is_ok_and(|meta| meta.is_file() && meta.len() > 0)
+            {
+                // SAFETY: the map is only ever read, and --mmap documents
+                // that the file mustn't shrink underneath it, which is the
+                // one way reading it could fault
+                let map = unsafe { memmap2::Mmap::map(&file) }
+                    .wrap_err_with(|| format!("failed to map {}", path.display()))?;
+                return Ok(Box::new(io::Cursor::new(map)));
+            }
+            Ok(Box::new(BufReader::new(file)))
+        }
+        None => {
+            if atty::is(Stream::Stdin) {
+                return Err(eyre!("input is a terminal, please pipe data via stdin!"));
+            }
+
+            Ok(Box::new(io::stdin()))
+        }
+    }
+}
+
+fn expected_size(source: Option<&Path>, kind: CompressionType, magic: &[u8]) -> Option<u64> {
+    match source {
+        // xz keeps its sizes in an index at the very end
+        Some(path) if kind == CompressionType::Xz && !is_url(path) => File::open(path)
+            .and_then(|mut file| c::xz_uncompressed_size(&mut file))
+            .ok()
+            .flatten(),
+        // and gzip its size modulo 2^32 in the last four bytes
+        Some(path) if kind == CompressionType::Gzip && !is_url(path) => File::open(path)
+            .and_then(|mut file| c::gzip_uncompressed_size(&mut file))
+            .ok()
+            .flatten(),
+        _ => c::uncompressed_size_hint(kind, magic),
+    }
+}
+
+// a line of --format json
+#[derive(serde::Serialize)]
+struct Detection<'a> {
+    file: Option<&'a Path>,
+    #[serde(rename = "type")]
+    kind: CompressionType,
+    uncompressed_size: Option<u64>,
+    detected_from: &'static str,
+}
+
+fn print_json(
+    source: Option<&Path>,
+    kind: CompressionType,
+    prefix: &[u8],
+    confidence: Confidence,
+    flags: &Flags,
+) -> Result<()> {
+    let detected_from = match confidence {
+        _ if flags.forced_input_type().is_some() => "forced",
+        Confidence::Certain => "magic",
+        // a hint names its type, a trial decode finds brotli on its own
+        Confidence::Guessed if CompressionType::from_hint(&flags.hint) == Some(kind) => "hint",
+        Confidence::Guessed => "trial",
+        Confidence::NoMatch | Confidence::Truncated { .. } => "none",
+    };
+    let detection = Detection {
+        file: source,
+        kind,
+        uncompressed_size: expected_size(source, kind, prefix),
+        detected_from,
+    };
+    println!("{}", serde_json::to_string(&detection)?);
+    Ok(())
+}
+
+// what --range reads to find the part of the file covering it
+enum SeekIndex {
+    Zstd(c::SeekTable),
+    Gzip(c::GzipIndex),
+}
+
+// seekable zstd keeps a table of its frames at the end, and gzip can have a
+// .gzi index beside it, so only the part covering the range needs to be read
+fn extract_range(flags: &Flags, range: Range<u64>) -> Result<()> {
+    let path = match flags.files.as_slice() {
+        [path] if !is_url(path) => path,
+        [_, _, ..] => return Err(eyre!("--range takes a single file")),
+        _ => return Err(eyre!("--range needs a seekable zstd file, not a stream")),
+    };
+    let mut file =
+        File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    let index = match &flags.index {
+        Some(index) => SeekIndex::Gzip(
+            File::open(index)
+                .map(BufReader::new)
+                .and_then(c::GzipIndex::read)
+                .wrap_err_with(|| format!("failed to read the index {}", index.display()))?,
+        ),
+        None => match c::SeekTable::read(&mut file)? {
+            Some(table) => SeekIndex::Zstd(table),
+            None => {
+                file.rewind()?;
+                let (kind, _) = c::detect_stream_characteristics(&mut file, flags)?;
+                return Err(if kind == CompressionType::Gzip {
+                    eyre!(
+                        "--range on gzip needs a .gzi index of {}, passed with --index",
+                        path.display()
+                    )
+                } else {
+                    eyre!(
+                        "--range needs a seekable zstd file, but {} has no seek table",
+                        path.display()
+                    )
+                });
+            }
+        },
+    };
+    let mut output: Box<dyn Write> = match &flags.output {
+        Some(output) => {
+            let output = output_path(output, &flags.files)?;
+            let file = File::create(&output)
+                .wrap_err_with(|| format!("failed to open {} for writing", output.display()))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => flags.output_buffer.wrap(guarded_stdout(flags)),
+    };
+    let result = match &index {
+        SeekIndex::Zstd(table) => c::decompress_range(&mut file, table, range, &mut output)
+            .wrap_err("failed decompressing Zstd stream"),
+        SeekIndex::Gzip(index) => c::decompress_gzip_range(&mut file, index, range, &mut output)
+            .wrap_err("failed decompressing Gzip stream"),
+    }
+    .and_then(|_| Ok(output.flush()?));
+
+    ignore_broken_pipe(result)
+}
+
+// binary output garbles a terminal, so it's refused there unless forced
+fn guarded_stdout(flags: &Flags) -> c::TerminalGuard<io::StdoutLock<'static>> {
+    let is_terminal = atty::is(Stream::Stdout) && !flags.force;
+    c::TerminalGuard::new(io::stdout().lock(), is_terminal)
+}
+
+// stdout closing early, e.g. `c big.gz | head`, is how pipelines normally end,
+// so exit quietly like other Unix tools do
+fn ignore_broken_pipe(result: Result<()>) -> Result<()> {
+    match result {
+        Err(report)
+            if report.chain().any(|cause| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+            }) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// `--output some/dir` names the file after the input, minus its compression
+// extension, like `gunzip` does next to the original
+fn output_path(output: &Path, inputs: &[PathBuf]) -> Result<PathBuf> {
+    if !output.is_dir() {
+        return Ok(output.to_path_buf());
     }
 
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
+    let input = match inputs {
+        [input] => input,
+        [] => {
+            return Err(eyre!(
+                "cannot name a file in {} when reading from stdin",
+                output.display()
+            ))
+        }
+        _ => {
+            return Err(eyre!(
+                "cannot name a file in {} after several inputs",
+                output.display()
+            ))
+        }
+    };
+    let name = Path::new(input.file_name().unwrap_or_default());
+    match extension_compression_type(name) {
+        Some(_) => Ok(output.join(name.file_stem().unwrap_or_default())),
+        _ => Err(eyre!(
+            "cannot derive an output name from {}: unknown compression extension",
+            input.display()
+        )),
+    }
+}
 
-    
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
+// e.g. `Zstd -> Xz` for a chain
+fn output_types(flags: &Flags) -> String {
+    match flags.output_type.as_slice() {
+        [] => CompressionType::None.to_string(),
+        kinds => kinds
+            .iter()
+            .map(|kind| kind.to_string())
+            .collect::<Vec<_>>()
+            .join(" -> "),
+    }
+}
 
-    let (kind, magic) = ctx::detect_stream_characteristics(&mut stdin, &flags)?;
-    // chain magic to stdin
-    let mut stdin = magic.chain(stdin);
+// extensions that say the file is plain, so there's nothing to warn about
+const PLAIN_EXTENSIONS: &[&str] = &[
+    "bin", "csv", "dat", "html", "img", "iso", "json", "log", "md", "out", "sql", "tar", "tsv",
+    "txt", "xml", "yaml", "yml",
+];
+
+fn output_type_for(path: &Path, quiet: bool) -> CompressionType {
+    extension_compression_type(path).unwrap_or_else(|| {
+        let plain = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_none_or(|extension| PLAIN_EXTENSIONS.contains(&extension));
+        if !quiet && !plain {
+            eprintln!(
+                "{} has no known compression extension, so the output is uncompressed",
+                path.display()
+            );
+            eprintln!("pass -o to pick an output format explicitly");
+        }
+        CompressionType::None
+    })
+}
+
+// brotli and raw deflate have no magic, so a file named for one is taken at
+// its word, like a URL's Content-Encoding. a hint that was given wins, and so
+// does any magic that matches
+fn hint_from_extension(path: &Path, flags: &mut Flags) {
+    if flags.hint != "unknown" {
+        return;
+    }
+    if let Some(kind @ (CompressionType::Brotli | CompressionType::Deflate)) =
+        extension_compression_type(path)
+    {
+        flags.hint = kind.to_possible_value().unwrap().get_name().into();
+    }
+}
+
+// of several codings, e.g. `gzip, br`, the last was applied last, so it's the
+// one the body starts with
+fn hint_from_content_encoding(encoding: &str, flags: &mut Flags) {
+    if flags.hint != "unknown" {
+        return;
+    }
+    let outermost = encoding.rsplit(',').next().unwrap_or_default().trim();
+    if CompressionType::from_hint(outermost).is_some() {
+        flags.hint = outermost.into();
+    }
+}
+
+fn extension_compression_type(path: &Path) -> Option<CompressionType> {
+    CompressionType::from_extension(path.extension()?.to_str()?)
+}
+
+// split the way a shell would, so `--exec "grep 'a b'"` passes `a b` whole
+fn split_command(command: &str, flag: &str) -> Result<(String, Vec<String>)> {
+    let mut args = shlex::split(command)
+        .ok_or_else(|| eyre!("{flag} has an unterminated quote or escape: {command}"))?;
+    if args.is_empty() {
+        return Err(eyre!("{flag} needs a command"));
+    }
+    let program = args.remove(0);
+    Ok((program, args))
+}
 
-    let mut context = Context::new_from_stream(&mut stdin, &mut stdout, kind, &flags)?;
-    context.translate_stream()
+fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
 }
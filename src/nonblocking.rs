@@ -0,0 +1,134 @@
+//! The async counterpart of [`decompress`](crate::decompress), for tokio
+//! readers and writers. Only gzip, zstd, brotli and xz input is supported.
+
+use std::io::{self, Cursor};
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, XzDecoder, ZstdDecoder};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::ctx::BROTLI_BUFFER_SIZE;
+use crate::detect::{
+    detect_compression_type, detect_stream_characteristics, BROTLI_TRIAL_SIZE, DETECT_BUFFER_SIZE,
+};
+use crate::{CompressionType, DecompressError, Flags};
+
+/// Detect the compression type of `input` and write its decompressed contents
+/// to `output`, like [`decompress`](crate::decompress) does for blocking
+/// streams.
+pub async fn decompress<R, W>(mut input: R, mut output: W) -> Result<(), DecompressError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let flags = Flags::default();
+    let mut prefix = vec![0; DETECT_BUFFER_SIZE];
+    let mut n = read_prefix(&mut input, &mut prefix).await?;
+    // brotli has no magic, so read as much as the blocking trial decode would
+    if n == DETECT_BUFFER_SIZE && detect_compression_type(&prefix, &flags) == CompressionType::None
+    {
+        prefix.resize(BROTLI_TRIAL_SIZE, 0);
+        n += read_prefix(&mut input, &mut prefix[n..]).await?;
+    }
+    prefix.truncate(n);
+
+    // the prefix is all in memory, so detection itself can't block
+    let (kind, mut peeked) = detect_stream_characteristics(prefix.as_slice(), &flags)?;
+    if peeked.peeked().is_empty() {
+        return Ok(());
+    }
+    // without anything detection dropped, like a BOM
+    let mut replay = Vec::new();
+    io::Read::read_to_end(&mut peeked, &mut replay)?;
+    let input = BufReader::new(Cursor::new(replay).chain(input));
+    let mut decoder: Box<dyn AsyncRead + Unpin> = match kind {
+        CompressionType::Gzip => {
+            let mut decoder = GzipDecoder::new(input);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        CompressionType::Zstd => {
+            let mut decoder = ZstdDecoder::new(input);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        CompressionType::Xz => {
+            let mut decoder = XzDecoder::new(input);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        CompressionType::Brotli => Box::new(BrotliDecoder::new(input)),
+        CompressionType::None => return Err(DecompressError::UnknownFormat),
+        kind => {
+            return Err(DecompressError::UnsupportedInput(format!(
+                "{kind} input isn't supported by the async API"
+            )))
+        }
+    };
+
+    let mut buffer = vec![0; BROTLI_BUFFER_SIZE];
+    let mut offset = 0;
+    loop {
+        let n = match decoder.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => return Err(DecompressError::from_codec(kind, offset, e)),
+        };
+        output.write_all(&buffer[..n]).await?;
+        offset += n as u64;
+    }
+    output.flush().await?;
+
+    Ok(())
+}
+
+// the async twin of detect's `read_prefix`
+async fn read_prefix<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match stream.read(&mut buffer[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use color_eyre::Result;
+
+    #[tokio::test]
+    async fn test_async_decompress_round_trips_gzip() -> Result<()> {
+        let expected = "this is a test\n".repeat(1000);
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        decompress(compressed_stream.as_slice(), &mut output_stream).await?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        // a BOM in front is skipped, not fed to the decoder
+        let with_bom = [b"\xef\xbb\xbf".as_slice(), &compressed_stream].concat();
+        let mut output_stream: Vec<u8> = Vec::new();
+        decompress(with_bom.as_slice(), &mut output_stream).await?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        let result = decompress(b"this is a test".as_slice(), &mut output_stream).await;
+        assert!(matches!(result, Err(DecompressError::UnknownFormat)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use std::io::{self, BufRead, Read};
+
+/// A stream with the bytes detection peeked at put back in front, so nothing
+/// is lost between detecting the compression type and decoding.
+pub struct PeekReader<R: Read> {
+    peeked: Vec<u8>,
+    pos: usize,
+    inner: R,
+}
+
+impl<R: Read> PeekReader<R> {
+    pub fn new(peeked: Vec<u8>, inner: R) -> Self {
+        Self {
+            peeked,
+            pos: 0,
+            inner,
+        }
+    }
+
+    /// Everything detection read from the start of the stream, whether or
+    /// not it has been read back out yet.
+    pub fn peeked(&self) -> &[u8] {
+        &self.peeked
+    }
+
+    /// The underlying stream, without whatever of the peeked prefix hasn't
+    /// been read back out yet.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rest = &self.peeked[self.pos..];
+        if rest.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for PeekReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos < self.peeked.len() {
+            return Ok(&self.peeked[self.pos..]);
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pos < self.peeked.len() {
+            self.pos = (self.pos + amt).min(self.peeked.len());
+        } else {
+            self.inner.consume(amt);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{self, BufRead, Read};
+use std::sync::RwLock;
+
+/// Wraps a stream of a custom format in a reader of its decoded contents. The
+/// stream starts at the magic, which is not stripped.
+pub type CustomDecoder = for<'a> fn(Box<dyn Read + 'a>) -> Box<dyn Read + 'a>;
+
+// in registration order, which is the order they're tried in
+static DECODERS: RwLock<Vec<(Vec<u8>, CustomDecoder)>> = RwLock::new(Vec::new());
+
+/// Registers a decoder for streams starting with `magic`, for in-house formats
+/// the built-in ones don't cover. Detection reports them as
+/// [`CompressionType::Custom`](crate::CompressionType::Custom), and they can
+/// only be decoded, not written.
+///
+/// The built-in magics are always checked first, so a custom one that starts
+/// with e.g. gzip's `1f 8b` is never reached. Custom formats are then tried in
+/// the order they were registered, and the first match wins. All of them come
+/// before the hint and brotli's trial decode, which only apply to input that
+/// matched no magic at all.
+///
+/// # Panics
+///
+/// If `magic` is empty, since it would match everything.
+pub fn register_decoder(magic: &[u8], decoder: CustomDecoder) {
+    assert!(!magic.is_empty(), "a custom format needs a magic");
+    DECODERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((magic.to_vec(), decoder));
+}
+
+// the first registered decoder whose magic starts `buffer`
+pub(crate) fn find(buffer: &[u8]) -> Option<CustomDecoder> {
+    DECODERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(magic, _)| buffer.starts_with(magic))
+        .map(|&(_, decoder)| decoder)
+}
+
+// so detection peeks far enough to see every custom magic whole
+pub(crate) fn longest_magic() -> usize {
+    DECODERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(magic, _)| magic.len())
+        .max()
+        .unwrap_or(0)
+}
+
+// whether `prefix` is the start of a registered magic, but not all of it
+pub(crate) fn could_be_cut_off(prefix: &[u8]) -> bool {
+    DECODERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|(magic, _)| magic.len() > prefix.len() && magic.starts_with(prefix))
+}
+
+// picks the decoder again from the start of `input`, which detection put back
+pub(crate) fn custom_decoder<'a, R: BufRead + 'a>(mut input: R) -> io::Result<Box<dyn Read + 'a>> {
+    let decoder = find(input.fill_buf()?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no registered decoder matches the input",
+        )
+    })?;
+    Ok(decoder(Box::new(input)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{detect_with_confidence, CompressionType, Confidence, Context, Flags};
+
+    // a made-up codec: its magic, then every byte xored with 0x5a
+    const XOR_MAGIC: &[u8] = b"XOR5A\x00";
+
+    struct XorReader<R: Read>(R);
+
+    impl<R: Read> Read for XorReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buf)?;
+            buf[..n].iter_mut().for_each(|byte| *byte ^= 0x5a);
+            Ok(n)
+        }
+    }
+
+    fn xor_decoder(mut input: Box<dyn Read + '_>) -> Box<dyn Read + '_> {
+        let mut magic = [0; XOR_MAGIC.len()];
+        input
+            .read_exact(&mut magic)
+            .expect("detection saw the magic");
+        Box::new(XorReader(input))
+    }
+
+    // registered with a gzip magic, which the built-in check always claims
+    fn shadowed_decoder(input: Box<dyn Read + '_>) -> Box<dyn Read + '_> {
+        input
+    }
+
+    #[test]
+    fn test_custom_format_is_detected_and_decoded() -> Result<()> {
+        register_decoder(XOR_MAGIC, xor_decoder);
+        register_decoder(&[0x1f, 0x8b], shadowed_decoder);
+
+        let expected = "this is a test\n".repeat(1000);
+        let mut compressed_stream = XOR_MAGIC.to_vec();
+        compressed_stream.extend(expected.bytes().map(|byte| byte ^ 0x5a));
+
+        let flags = Flags::default();
+        let (kind, mut input_stream, confidence) =
+            detect_with_confidence(compressed_stream.as_slice(), &flags)?;
+        assert_eq!(
+            (CompressionType::Custom, Confidence::Certain),
+            (kind, confidence)
+        );
+        let mut output_stream = Vec::new();
+        Context::new_from_stream(&mut input_stream, &mut output_stream, kind, &flags)?
+            .translate_stream()?;
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        let mut gzip = Vec::new();
+        let mut encoder = flate2::write::GzEncoder::new(&mut gzip, flate2::Compression::default());
+        encoder.write_all(expected.as_bytes())?;
+        encoder.finish()?;
+        let (kind, _, _) = detect_with_confidence(gzip.as_slice(), &flags)?;
+        assert_eq!(CompressionType::Gzip, kind);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+const SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+const SKIPPABLE_HEADER_SIZE: u64 = 8;
+const FOOTER_SIZE: u64 = 9;
+const CHECKSUM_FLAG: u8 = 0x80;
+
+struct Frame {
+    compressed_offset: u64,
+    compressed_size: u64,
+    decompressed_offset: u64,
+    decompressed_size: u64,
+}
+
+/// The seek table of a zstd file in the seekable format, which is a series of
+/// independent frames followed by a skippable frame listing their sizes.
+pub struct SeekTable {
+    frames: Vec<Frame>,
+}
+
+impl SeekTable {
+    /// Reads the seek table from the end of `file`, or `None` if there isn't
+    /// one.
+    pub fn read<R: Read + Seek>(file: &mut R) -> io::Result<Option<Self>> {
+        let len = file.seek(SeekFrom::End(0))?;
+        if len < SKIPPABLE_HEADER_SIZE + FOOTER_SIZE {
+            return Ok(None);
+        }
+
+        let mut footer = [0; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(len - FOOTER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        if u32_at(&footer, 5) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+        let frame_count = u32_at(&footer, 0) as u64;
+        let entry_size = if footer[4] & CHECKSUM_FLAG != 0 {
+            12
+        } else {
+            8
+        };
+
+        let table_size = frame_count * entry_size;
+        let Some(table_start) = (len - FOOTER_SIZE - SKIPPABLE_HEADER_SIZE).checked_sub(table_size)
+        else {
+            return Ok(None);
+        };
+        let mut header = [0; SKIPPABLE_HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(table_start))?;
+        file.read_exact(&mut header)?;
+        if u32_at(&header, 0) != SEEK_TABLE_MAGIC
+            || u32_at(&header, 4) as u64 != table_size + FOOTER_SIZE
+        {
+            return Ok(None);
+        }
+
+        let mut entries = vec![0; table_size as usize];
+        file.read_exact(&mut entries)?;
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+        for entry in entries.chunks_exact(entry_size as usize) {
+            let frame = Frame {
+                compressed_offset,
+                compressed_size: u32_at(entry, 0) as u64,
+                decompressed_offset,
+                decompressed_size: u32_at(entry, 4) as u64,
+            };
+            compressed_offset += frame.compressed_size;
+            decompressed_offset += frame.decompressed_size;
+            frames.push(frame);
+        }
+        if compressed_offset > table_start {
+            return Ok(None);
+        }
+
+        Ok(Some(Self { frames }))
+    }
+
+    pub fn decompressed_size(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| {
+            frame.decompressed_offset + frame.decompressed_size
+        })
+    }
+}
+
+/// Decompresses only the frames covering `range` of the decompressed data and
+/// writes that range to `output`. A range reaching past the end is cut short.
+pub fn decompress_range<R: Read + Seek, W: Write>(
+    file: &mut R,
+    table: &SeekTable,
+    range: Range<u64>,
+    output: &mut W,
+) -> io::Result<u64> {
+    let end = range.end.min(table.decompressed_size());
+    if range.start >= end {
+        return Ok(0);
+    }
+
+    let covering: Vec<&Frame> = table
+        .frames
+        .iter()
+        .skip_while(|frame| frame.decompressed_offset + frame.decompressed_size <= range.start)
+        .take_while(|frame| frame.decompressed_offset < end)
+        .collect();
+    let (Some(first), Some(last)) = (covering.first(), covering.last()) else {
+        return Ok(0);
+    };
+
+    file.seek(SeekFrom::Start(first.compressed_offset))?;
+    let compressed_size = last.compressed_offset + last.compressed_size - first.compressed_offset;
+    let mut decoder = zstd::Decoder::with_buffer(BufReader::new(file.take(compressed_size)))?;
+    io::copy(
+        &mut (&mut decoder).take(range.start - first.decompressed_offset),
+        &mut io::sink(),
+    )?;
+    io::copy(&mut decoder.take(end - range.start), output)
+}
+
+fn u32_at(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::Result;
+
+    use super::*;
+
+    // three frames of ten bytes each, then the seek table
+    fn seekable_stream(checksums: bool) -> Result<Vec<u8>> {
+        let mut stream = Vec::new();
+        let mut entries = Vec::new();
+        for chunk in [b"0123456789", b"abcdefghij", b"ABCDEFGHIJ"] {
+            let frame = zstd::encode_all(chunk.as_slice(), 3)?;
+            entries.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            entries.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            if checksums {
+                entries.extend_from_slice(&[0; 4]);
+            }
+            stream.extend_from_slice(&frame);
+        }
+
+        stream.extend_from_slice(&SEEK_TABLE_MAGIC.to_le_bytes());
+        stream.extend_from_slice(&(entries.len() as u32 + FOOTER_SIZE as u32).to_le_bytes());
+        stream.extend_from_slice(&entries);
+        stream.extend_from_slice(&3u32.to_le_bytes());
+        stream.push(if checksums { CHECKSUM_FLAG } else { 0 });
+        stream.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        Ok(stream)
+    }
+
+    #[test]
+    fn test_range_is_extracted_from_covering_frames() -> Result<()> {
+        for checksums in [false, true] {
+            let stream = seekable_stream(checksums)?;
+            let mut file = io::Cursor::new(&stream);
+            let table = SeekTable::read(&mut file)?.expect("has a seek table");
+            assert_eq!(30, table.decompressed_size());
+
+            for (range, expected) in [
+                (5..15, b"56789abcde".as_slice()),
+                (10..20, b"abcdefghij"),
+                (28..100, b"IJ"),
+                (7..7, b""),
+            ] {
+                let mut output = Vec::new();
+                decompress_range(&mut file, &table, range, &mut output)?;
+                assert_eq!(expected, output);
+            }
+
+            // it's still an ordinary zstd stream too
+            assert_eq!(
+                b"0123456789abcdefghijABCDEFGHIJ".as_slice(),
+                zstd::decode_all(stream.as_slice())?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_zstd_has_no_seek_table() -> Result<()> {
+        let stream = zstd::encode_all(b"this is a test".as_slice(), 3)?;
+        assert!(SeekTable::read(&mut io::Cursor::new(&stream))?.is_none());
+
+        Ok(())
+    }
+}
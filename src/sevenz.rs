@@ -0,0 +1,167 @@
+use std::io::{self, Cursor, Read};
+
+use sevenz_rust::{Archive, Password, SevenZReader};
+
+use crate::{CompressionType, DecompressError};
+
+/// The contents of one file in a 7z archive. 7z isn't a stream: the header
+/// saying where everything is comes at the end, and files are often packed
+/// together, so the whole archive is read into memory first. Without a
+/// `name`, the archive must hold exactly one file. Folders packed with LZMA,
+/// LZMA2, bzip2 or stored are supported, behind any of the BCJ or delta
+/// filters, but not BCJ2 or encryption.
+///
+/// The file is decoded into memory too, so with a `max_output` it stops one
+/// byte past it, leaving the caller's limit to fail on that byte. Anything
+/// after the archive's header is an error, unless `ignore_trailing_garbage`.
+pub fn read_7z_entry<R: Read>(
+    input: &mut R,
+    name: Option<&str>,
+    max_output: Option<u64>,
+    ignore_trailing_garbage: bool,
+) -> Result<Vec<u8>, DecompressError> {
+    let mut archive = Vec::new();
+    input.read_to_end(&mut archive)?;
+    if let Some(end) = archive_len(&archive).filter(|&end| end < archive.len() as u64) {
+        if !ignore_trailing_garbage {
+            return Err(DecompressError::TrailingData {
+                format: CompressionType::SevenZ,
+            });
+        }
+        archive.truncate(end as usize);
+    }
+    let len = archive.len() as u64;
+    let mut archive =
+        SevenZReader::new(Cursor::new(archive), len, Password::empty()).map_err(from_7z)?;
+
+    let files = file_names(archive.archive());
+    let name = match (name, files.as_slice()) {
+        (Some(name), files) if files.contains(&name) => name.to_owned(),
+        (Some(name), _) => return Err(DecompressError::SevenZEntryNotFound { name: name.into() }),
+        (None, [only]) => (*only).to_owned(),
+        (None, files) => {
+            return Err(DecompressError::SevenZEntryNeeded {
+                entries: files.join(", "),
+            })
+        }
+    };
+
+    // files packed together are decoded in order, so the ones before this
+    // one are read through too, and their CRCs checked on the way
+    let mut data = None;
+    archive
+        .for_each_entries(|entry, contents| {
+            if entry.is_directory() || data.is_some() || entry.name() != name {
+                io::copy(contents, &mut io::sink())?;
+                return Ok(true);
+            }
+            let mut contents_read = Vec::new();
+            match max_output {
+                Some(limit) => contents
+                    .take(limit.saturating_add(1))
+                    .read_to_end(&mut contents_read)?,
+                None => contents.read_to_end(&mut contents_read)?,
+            };
+            data = Some(contents_read);
+            Ok(false)
+        })
+        .map_err(from_7z)?;
+
+    data.ok_or(DecompressError::SevenZEntryNotFound { name })
+}
+
+/// The names of the files in a 7z archive, in the order it holds them, read
+/// from its header without decoding any of them.
+pub fn list_7z_entries<R: Read>(input: &mut R) -> Result<Vec<String>, DecompressError> {
+    let mut archive = Vec::new();
+    input.read_to_end(&mut archive)?;
+    let len = archive.len() as u64;
+    let archive = Archive::read(&mut Cursor::new(archive), len, &[]).map_err(from_7z)?;
+    Ok(file_names(&archive).into_iter().map(Into::into).collect())
+}
+
+// the signature header says where the header at the end starts and how long
+// it is, so where the archive ends. None if it's too short or damaged to say,
+// which the crate reports itself
+fn archive_len(archive: &[u8]) -> Option<u64> {
+    let crc = u32::from_le_bytes(archive.get(8..12)?.try_into().ok()?);
+    if crc32fast::hash(archive.get(12..32)?) != crc {
+        return None;
+    }
+    let offset = u64::from_le_bytes(archive.get(12..20)?.try_into().ok()?);
+    let size = u64::from_le_bytes(archive.get(20..28)?.try_into().ok()?);
+    32u64.checked_add(offset)?.checked_add(size)
+}
+
+// directories aren't files that can be extracted
+fn file_names(archive: &Archive) -> Vec<&str> {
+    archive
+        .files
+        .iter()
+        .filter(|entry| !entry.is_directory())
+        .map(|entry| entry.name())
+        .collect()
+}
+
+fn from_7z(error: sevenz_rust::Error) -> DecompressError {
+    match error {
+        sevenz_rust::Error::ExternalUnsupported
+        | sevenz_rust::Error::UnsupportedCompressionMethod(_)
+        | sevenz_rust::Error::Unsupported(_)
+        | sevenz_rust::Error::PasswordRequired
+        | sevenz_rust::Error::MaybeBadPassword(_)
+        | sevenz_rust::Error::MaxMemLimited { .. } => {
+            DecompressError::UnsupportedInput(format!("this 7z archive isn't supported: {error}"))
+        }
+        // the archive is already in memory, so any error reading it is the
+        // archive's
+        error => DecompressError::CorruptArchive {
+            format: CompressionType::SevenZ,
+            source: io::Error::new(io::ErrorKind::InvalidData, error),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use color_eyre::Result;
+
+    #[test]
+    fn test_plain_and_packed_headers() -> Result<()> {
+        let expected = include_bytes!("../fixtures/lzw.txt");
+        // LZMA, with the header as is
+        let archive = include_bytes!("../fixtures/lzw.txt.7z");
+        assert_eq!(
+            expected.as_slice(),
+            read_7z_entry(&mut archive.as_slice(), None, None, false)?
+        );
+        // LZMA2, with the header packed too
+        let archive = include_bytes!("../fixtures/several.7z");
+        assert_eq!(
+            expected.as_slice(),
+            read_7z_entry(&mut archive.as_slice(), Some("lzw.txt"), None, false)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_damage_is_caught() {
+        let archive = include_bytes!("../fixtures/lzw.txt.7z");
+        // in the packed data, the header, and the signature header
+        for offset in [100, archive.len() - 10, 20] {
+            let mut damaged = archive.to_vec();
+            damaged[offset] ^= 0x55;
+            assert!(
+                matches!(
+                    read_7z_entry(&mut damaged.as_slice(), None, None, false),
+                    Err(DecompressError::CorruptArchive { .. })
+                ),
+                "{offset}"
+            );
+        }
+        let truncated = &archive[..archive.len() / 2];
+        assert!(read_7z_entry(&mut &truncated[..], None, None, false).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{self, Read, Write};
+
+/// Writes a copy of everything read through it to `copy`, e.g. to keep the
+/// compressed input while decompressing it. Wrap the stream before detection
+/// so the peeked prefix is copied too, and read it to the end afterwards if
+/// the decoder might stop short of it.
+pub struct TeeInput<R: Read, W: Write> {
+    inner: R,
+    copy: W,
+}
+
+impl<R: Read, W: Write> TeeInput<R, W> {
+    pub fn new(inner: R, copy: W) -> Self {
+        Self { inner, copy }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.inner, self.copy)
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeInput<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{detect_stream_characteristics, CompressionType, Context, Flags};
+
+    #[test]
+    fn test_saved_input_matches_the_original() -> Result<()> {
+        let expected = "this is a test\n".repeat(10_000);
+        let mut compressed_stream = Vec::new();
+        {
+            let mut encoder = flate2::write::GzEncoder::new(
+                &mut compressed_stream,
+                flate2::Compression::default(),
+            );
+            encoder.write_all(expected.as_bytes())?;
+        }
+        // left unread by the decoder when told to ignore it
+        compressed_stream.extend_from_slice(b"trailing garbage");
+
+        let flags = Flags {
+            quiet: true,
+            ignore_trailing_garbage: true,
+            ..Default::default()
+        };
+        let mut saved = Vec::new();
+        let input = TeeInput::new(compressed_stream.as_slice(), &mut saved);
+        let (kind, mut input) = detect_stream_characteristics(input, &flags)?;
+        assert_eq!(CompressionType::Gzip, kind);
+        let mut output_stream = Vec::new();
+        Context::new_from_stream(&mut input, &mut output_stream, kind, &flags)?
+            .translate_stream()?;
+        io::copy(&mut input, &mut io::sink())?;
+        drop(input);
+
+        assert_eq!(expected.as_bytes(), output_stream);
+        assert_eq!(compressed_stream, saved);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+use crate::detect::looks_like_text;
+
+// enough to tell text apart, and little enough that a terminal user sees
+// output promptly
+const SAMPLE_SIZE: usize = 1024;
+
+/// Refuses to write binary data to a terminal, where it garbles the display,
+/// like gzip does. The first bytes are held back until there are enough to
+/// tell whether they're text, or until a flush. Text is passed on, and from
+/// then on everything else is too. With `is_terminal` false it passes every
+/// write straight through.
+pub struct TerminalGuard<W: Write> {
+    inner: W,
+    // the sample so far, until it's been judged
+    held: Option<Vec<u8>>,
+}
+
+impl<W: Write> TerminalGuard<W> {
+    pub fn new(inner: W, is_terminal: bool) -> Self {
+        Self {
+            inner,
+            held: is_terminal.then(Vec::new),
+        }
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        let Some(held) = self.held.take() else {
+            return Ok(());
+        };
+        if !held.is_empty() && !looks_like_text(&held) {
+            // stay shut, so nothing leaks out on a later write
+            self.held = Some(held);
+            return Err(io::Error::other(
+                "refusing to write binary data to a terminal, redirect it to a file or pass --force",
+            ));
+        }
+        self.inner.write_all(&held)
+    }
+}
+
+impl<W: Write> Write for TerminalGuard<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.held {
+            Some(held) => {
+                let n = buf.len().min(SAMPLE_SIZE - held.len());
+                held.extend_from_slice(&buf[..n]);
+                if held.len() == SAMPLE_SIZE {
+                    self.release()?;
+                }
+                Ok(n)
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.release()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_binary_is_refused_only_on_a_terminal() -> Result<()> {
+        let text = "this is a test\n".repeat(1000);
+        let binary: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+
+        let mut output = Vec::new();
+        let mut guard = TerminalGuard::new(&mut output, true);
+        guard.write_all(text.as_bytes())?;
+        guard.flush()?;
+        assert_eq!(text.as_bytes(), output);
+
+        // less than a sample, so it's judged on the flush
+        let mut output = Vec::new();
+        let mut guard = TerminalGuard::new(&mut output, true);
+        guard.write_all(b"short")?;
+        guard.flush()?;
+        assert_eq!(b"short", output.as_slice());
+
+        let mut output = Vec::new();
+        let mut guard = TerminalGuard::new(&mut output, true);
+        let err = guard.write_all(&binary).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{err}");
+        assert!(guard.flush().is_err());
+        assert!(output.is_empty());
+
+        // piped, so nothing's checked
+        let mut output = Vec::new();
+        let mut guard = TerminalGuard::new(&mut output, false);
+        guard.write_all(&binary)?;
+        guard.flush()?;
+        assert_eq!(binary, output);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Compares two byte streams that arrive in chunks of unrelated sizes, only
+/// holding on to whichever one is currently ahead.
+#[derive(Default)]
+pub struct Verifier {
+    expected: VecDeque<u8>,
+    actual: VecDeque<u8>,
+    offset: u64,
+    mismatch: Option<u64>,
+}
+
+impl Verifier {
+    fn push_expected(&mut self, buf: &[u8]) {
+        if self.mismatch.is_none() {
+            self.expected.extend(buf);
+            self.compare();
+        }
+    }
+
+    fn push_actual(&mut self, buf: &[u8]) {
+        if self.mismatch.is_none() {
+            self.actual.extend(buf);
+            self.compare();
+        }
+    }
+
+    fn compare(&mut self) {
+        while let (Some(expected), Some(actual)) = (self.expected.front(), self.actual.front()) {
+            if expected != actual {
+                self.mismatch = Some(self.offset);
+                self.expected.clear();
+                self.actual.clear();
+                return;
+            }
+            self.expected.pop_front();
+            self.actual.pop_front();
+            self.offset += 1;
+        }
+    }
+
+    /// The offset of the first difference, once both streams are complete.
+    pub fn mismatch(&self) -> Option<u64> {
+        self.mismatch.or_else(|| {
+            (!self.expected.is_empty() || !self.actual.is_empty()).then_some(self.offset)
+        })
+    }
+}
+
+/// Feeds everything read through it to a [`Verifier`] as the expected stream.
+pub struct TeeReader<R: Read> {
+    inner: R,
+    verifier: Rc<RefCell<Verifier>>,
+}
+
+impl<R: Read> TeeReader<R> {
+    pub fn new(inner: R, verifier: Rc<RefCell<Verifier>>) -> Self {
+        Self { inner, verifier }
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.verifier.borrow_mut().push_expected(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Feeds everything written to it to a [`Verifier`] as the actual stream.
+pub struct VerifyWriter(pub Rc<RefCell<Verifier>>);
+
+impl Write for VerifyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().push_actual(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
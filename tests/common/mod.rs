@@ -0,0 +1,29 @@
+// shared by the integration tests, which don't all use everything
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use color_eyre::Result;
+
+pub const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+
+pub fn fixture(name: &str) -> String {
+    format!("{FIXTURES}/{name}")
+}
+
+// the binary, fed `stdin`
+pub fn run(args: &[&str], stdin: &[u8]) -> Result<Output> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(stdin)?;
+    Ok(child.wait_with_output()?)
+}
@@ -0,0 +1,27 @@
+use color_eyre::Result;
+
+mod common;
+use common::{fixture, run};
+
+#[test]
+fn test_count_lines_and_bytes() -> Result<()> {
+    // `seq -f 'line %g' 1 100 | gzip`
+    let lines = fixture("lines.txt.gz");
+    let output = run(&["--count", &lines], b"")?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        format!("100 792 {lines}\n"),
+        String::from_utf8(output.stdout)?
+    );
+
+    // plain input is counted as it is, and a total follows
+    let plain = fixture("legacy-v07.txt");
+    let output = run(&["--count", &lines, &plain], b"")?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        format!("100 792 {lines}\n1 33 {plain}\n101 825 total\n"),
+        String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
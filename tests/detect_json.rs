@@ -0,0 +1,68 @@
+use std::io::Write;
+
+use color_eyre::Result;
+use serde_json::{json, Value};
+
+mod common;
+use common::fixture;
+
+// every line of stdout, each of which has to be JSON
+fn run(args: &[&str], stdin: &[u8]) -> Result<Vec<Value>> {
+    let output = common::run(args, stdin)?;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?)
+}
+
+#[test]
+fn test_detection_as_json() -> Result<()> {
+    let lzip = fixture("lzip.txt.lz");
+    let plain = fixture("lzw.txt");
+    for mode in ["--detect-only", "--list"] {
+        let found = run(&[mode, "--format", "json", &lzip, &plain], b"")?;
+        assert_eq!(
+            vec![
+                json!({
+                    "file": lzip,
+                    "type": "lzip",
+                    "uncompressed_size": null,
+                    "detected_from": "magic",
+                }),
+                json!({
+                    "file": plain,
+                    "type": "none",
+                    "uncompressed_size": null,
+                    "detected_from": "none",
+                }),
+            ],
+            found,
+            "{mode}"
+        );
+    }
+
+    let expected = "this is a test\n".repeat(100);
+    let mut brotli = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut brotli, 4096, 9, 22);
+        encoder.write_all(expected.as_bytes())?;
+    }
+    let found = run(&["--list", "--format", "json"], &brotli)?;
+    assert_eq!(json!("trial"), found[0]["detected_from"]);
+    assert_eq!(json!(null), found[0]["file"]);
+    // --detect-only doesn't read far enough for the trial
+    let found = run(&["--detect-only", "--format", "json"], &brotli)?;
+    assert_eq!(json!("none"), found[0]["type"]);
+    let found = run(&["br", "--detect-only", "--format", "json"], &brotli)?;
+    assert_eq!(json!("hint"), found[0]["detected_from"]);
+
+    // the frame header records the size when it's known up front
+    let zstd = zstd::bulk::compress(expected.as_bytes(), 3)?;
+    let found = run(&["--list", "--format", "json"], &zstd)?;
+    assert_eq!(json!("zstd"), found[0]["type"]);
+    assert_eq!(json!(expected.len()), found[0]["uncompressed_size"]);
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use color_eyre::Result;
+
+mod common;
+use common::{fixture, run};
+
+#[test]
+fn test_numeric_exit_codes() -> Result<()> {
+    let lzip = fixture("lzip.txt.lz");
+    let corrupt = fixture("crc-mismatch.txt.gz");
+    let plain = fixture("lzw.txt");
+    let missing = fixture("missing.gz");
+
+    for (args, expected) in [
+        (vec![lzip.as_str()], 0),
+        // not an error without --decompress-only, it's passed through
+        (vec![plain.as_str()], 0),
+        (vec!["--decompress-only", &plain], 2),
+        (vec![corrupt.as_str()], 3),
+        (vec!["gzip", &missing], 4),
+        // a lone path is a file too, even if it's missing
+        (vec![missing.as_str()], 4),
+        (vec!["missing.gz"], 4),
+        (vec!["--zstd-info", &lzip], 1),
+        (vec!["--test", &lzip, &corrupt], 3),
+    ] {
+        let output = run(&[&["--numeric-exit"], args.as_slice()].concat(), b"")?;
+        assert_eq!(Some(expected), output.status.code(), "{args:?}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_errors_exit_with_one_by_default() -> Result<()> {
+    let corrupt = fixture("crc-mismatch.txt.gz");
+    for args in [
+        vec![corrupt.as_str()],
+        vec!["--decompress-only"],
+        vec!["--test", &corrupt],
+    ] {
+        let output = run(&args, b"this is a test\n")?;
+        assert_eq!(Some(1), output.status.code(), "{args:?}");
+        assert!(!output.stderr.is_empty());
+    }
+
+    Ok(())
+}
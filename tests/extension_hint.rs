@@ -0,0 +1,48 @@
+use std::fs;
+use std::io::Write;
+
+use color_eyre::Result;
+
+mod common;
+use common::run;
+
+#[test]
+fn test_br_extension_is_a_hint() -> Result<()> {
+    // too much to be caught by the trial decode, which would find it anyway
+    let mut state = 1u32;
+    let expected: Vec<u8> = (0..100_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let mut compressed_stream = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed_stream, 4096, 5, 22);
+        encoder.write_all(&expected)?;
+    }
+    assert!(compressed_stream.len() > 64 * 1024);
+
+    let dir = std::env::temp_dir().join(format!("c-extension-hint-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let named = dir.join("data.br");
+    let unnamed = dir.join("data");
+    fs::write(&named, &compressed_stream)?;
+    fs::write(&unnamed, &compressed_stream)?;
+
+    let output = run(&[named.to_str().unwrap()], b"")?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(expected, output.stdout);
+
+    // without the extension it's passed through, as before
+    let output = run(&["--quiet", unnamed.to_str().unwrap()], b"")?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(compressed_stream, output.stdout);
+
+    // and stdin has no name to go by
+    let output = run(&["--quiet"], &compressed_stream)?;
+    assert_eq!(compressed_stream, output.stdout);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use color_eyre::Result;
+
+mod common;
+use common::run;
+
+#[test]
+fn test_exec_command_is_split_like_a_shell() -> Result<()> {
+    // the quoted script reaches `sh -c` as one argument
+    let output = run(&["--exec", "sh -c 'tr a-z A-Z'"], b"this is a test")?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(b"THIS IS A TEST".as_slice(), output.stdout);
+
+    let output = run(&["--exec", "sh -c 'tr a-z"], b"this is a test")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("unterminated quote"));
+
+    Ok(())
+}
+
+#[test]
+fn test_pipe_to_command_is_split_like_a_shell() -> Result<()> {
+    let output = run(
+        &["--pipe-to", "sh -c 'tr a-z A-Z; exit 7'"],
+        b"this is a test",
+    )?;
+    assert_eq!(Some(7), output.status.code(), "{output:?}");
+    assert_eq!(b"THIS IS A TEST".as_slice(), output.stdout);
+
+    Ok(())
+}
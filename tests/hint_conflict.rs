@@ -0,0 +1,32 @@
+use color_eyre::Result;
+
+mod common;
+use common::{fixture, run};
+
+#[test]
+fn test_hint_and_magic_disagree() -> Result<()> {
+    let gzip = std::fs::read(fixture("lines.txt.gz"))?;
+    let expected: String = (1..=100).map(|n| format!("line {n}\n")).collect();
+
+    // the magic wins, with a warning
+    let output = run(&["brotli"], &gzip)?;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(expected.as_bytes(), output.stdout);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("hint says Brotli but magic indicates Gzip, using Gzip"),
+        "{stderr}"
+    );
+
+    let output = run(&["brotli", "--quiet"], &gzip)?;
+    assert!(output.stderr.is_empty(), "{output:?}");
+    let output = run(&["gzip"], &gzip)?;
+    assert!(output.stderr.is_empty(), "{output:?}");
+
+    // forcing the type is the one way to override the magic
+    let output = run(&["--force-input-type", "brotli"], &gzip)?;
+    assert!(!output.status.success());
+    assert!(!String::from_utf8(output.stderr)?.contains("hint says"));
+
+    Ok(())
+}
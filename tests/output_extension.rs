@@ -0,0 +1,30 @@
+use std::fs;
+
+use color_eyre::Result;
+
+mod common;
+use common::{fixture, run};
+
+#[test]
+fn test_plain_output_names_dont_warn() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("c-output-extension-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let lines = fixture("lines.txt.gz");
+    let expected: String = (1..=100).map(|n| format!("line {n}\n")).collect();
+
+    for (name, warns) in [("notes.txt", false), ("notes", false), ("notes.bz3", true)] {
+        let path = dir.join(name);
+        let output = run(&[&lines, "--output", path.to_str().unwrap()], b"")?;
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(expected, fs::read_to_string(&path)?, "{name}");
+        let stderr = String::from_utf8(output.stderr)?;
+        assert_eq!(
+            warns,
+            stderr.contains("no known compression extension"),
+            "{name}: {stderr}"
+        );
+    }
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}